@@ -0,0 +1,7 @@
+#!/bin/bash
+
+curl -X POST \
+    -H "Content-Type: application/json" \
+    -H "Authorization: Bearer 654321" \
+    -d '{"metric": "price", "symbol": "BTC", "op": "above", "threshold": 70000, "hysteresis": 500, "cooldown": 3600, "webhook": "http://127.0.0.1:9000/hook"}' \
+    localhost:8004/alerts
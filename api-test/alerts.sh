@@ -0,0 +1,3 @@
+#!/bin/bash
+
+curl -H "Authorization: Bearer 654321" localhost:8004/alerts
@@ -0,0 +1,3 @@
+#!/bin/bash
+
+curl localhost:8004/health
@@ -0,0 +1,3 @@
+#!/bin/bash
+
+curl "localhost:8004/market/000001.SS/history?interval=1d"
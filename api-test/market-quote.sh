@@ -0,0 +1,3 @@
+#!/bin/bash
+
+curl "localhost:8004/market/quote?codes=USDCNH.OTC,US500.OTC"
@@ -0,0 +1,6 @@
+#!/bin/bash
+
+curl -X POST \
+    -H "Content-Type: application/json" \
+    -d '[{"symbol": "BTC", "amount": 0.5, "cost_basis": 20000}, {"symbol": "ETH", "amount": 10}]' \
+    "localhost:8004/cryptocurrency/portfolio?currency=CNY"
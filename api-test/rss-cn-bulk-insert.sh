@@ -0,0 +1,10 @@
+#!/bin/bash
+
+# usage: ./rss-cn-bulk-insert.sh <file.json> [atomic|upsert]
+# file.json: [{"uuid": "optional", "data": {"name": "...", "url": "..."}}]
+
+curl -X POST \
+    -H "Content-Type: application/json" \
+    -H "Authorization: Bearer 654321" \
+    --data-binary "@$1" \
+    "localhost:8004/rssbox/rss/list/bulk/cn?mode=${2:-atomic}"
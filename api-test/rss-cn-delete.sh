@@ -1,5 +1,5 @@
 #!/bin/bash
 
 curl -X DELETE \
-    -H "authorization: Bearer 123456" \
+    -H "authorization: Bearer 654321" \
     localhost:8004/rssbox/rss/list/cn/$1
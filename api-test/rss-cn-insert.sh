@@ -6,5 +6,6 @@ data='{"name":"'$random_name'", "url":"'${random_data}'"}'
 
 curl -X POST \
     -H "Content-Type: application/json" \
+    -H "Authorization: Bearer 123456" \
     -d "$data" \
     localhost:8004/rssbox/rss/list/cn
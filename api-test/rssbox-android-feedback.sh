@@ -5,6 +5,7 @@ data='{"appid": "test", "type": "feedback", "data":"'${random_str}'"}'
 
 curl -X POST \
     -H "Content-Type: application/json" \
+    -H "Authorization: Bearer 123456" \
     -d "$data" \
     localhost:8004/rssbox/android/feedback
 
@@ -1,3 +1,3 @@
 #!/bin/bash
 
-curl localhost:8004/rssbox/android/feedbacks
+curl -H "Authorization: Bearer 654321" localhost:8004/rssbox/android/feedbacks
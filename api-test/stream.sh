@@ -0,0 +1,3 @@
+#!/bin/bash
+
+curl -N "localhost:8004/stream?topics=market,stats,latest"
@@ -0,0 +1,7 @@
+#!/bin/bash
+
+curl -X POST \
+    -H "Content-Type: application/json" \
+    -H "Authorization: Bearer 654321" \
+    -d '{"name": "'$1'", "scopes": ["feedback:read"], "expires_in": 2592000}' \
+    localhost:8004/admin/tokens
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+curl -X DELETE \
+    -H "Authorization: Bearer 654321" \
+    localhost:8004/admin/tokens/$1
@@ -0,0 +1,97 @@
+use crate::{controller::token::unknown_scopes, db::token};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+
+const USAGE: &str = "usage:
+    apisvr token create <name> --scope <scope>[,<scope>...] [--expires-days <days>]
+    apisvr token list
+    apisvr token revoke <id>
+    apisvr token rotate <id>";
+
+// Returns false when there is no command and the server should start.
+pub async fn run(args: &[String]) -> bool {
+    if args.is_empty() {
+        return false;
+    }
+
+    if let Err(e) = command(args).await {
+        eprintln!("error: {e:#}\n\n{USAGE}");
+        std::process::exit(1);
+    }
+    true
+}
+
+async fn command(args: &[String]) -> Result<()> {
+    let args = args.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        ["token", "create", name, options @ ..] => create(name, options).await,
+        ["token", "list"] => list().await,
+        ["token", "revoke", id] => {
+            let token = token::revoke(id.parse().context("invalid token id")?).await?;
+            println!("revoked {} ({})", token.name, token.id);
+            Ok(())
+        }
+        ["token", "rotate", id] => {
+            let (token, secret) = token::rotate(id.parse().context("invalid token id")?).await?;
+            println!(
+                "rotated {} ({}), the old secret no longer works",
+                token.name, token.id
+            );
+            println!("secret, it is shown only once: {secret}");
+            Ok(())
+        }
+        _ => bail!("unknown command: {}", args.join(" ")),
+    }
+}
+
+async fn create(name: &str, mut options: &[&str]) -> Result<()> {
+    let (mut scopes, mut expires_at) = (vec![], None);
+
+    while let [option, value, rest @ ..] = options {
+        match *option {
+            "--scope" => scopes.extend(value.split(',').filter(|s| !s.is_empty())),
+            "--expires-days" => {
+                let days: i64 = value.parse().context("invalid --expires-days")?;
+                expires_at = Some(Utc::now().timestamp() + days * 24 * 3600);
+            }
+            _ => bail!("unknown option: {option}"),
+        }
+        options = rest;
+    }
+
+    if !options.is_empty() {
+        bail!("missing value for {}", options[0]);
+    }
+
+    let unknown = unknown_scopes(&scopes);
+    if scopes.is_empty() || !unknown.is_empty() {
+        bail!("need at least one known scope, unknown: {unknown:?}");
+    }
+
+    let (token, secret) = token::create(name, &scopes, expires_at).await?;
+    println!(
+        "created {} ({}) with scopes: {}",
+        token.name, token.id, token.scopes
+    );
+    println!("secret, it is shown only once: {secret}");
+    Ok(())
+}
+
+async fn list() -> Result<()> {
+    let now = Utc::now().timestamp();
+    for t in token::select_all().await? {
+        println!(
+            "{}\t{}\t{}\t{}",
+            t.id,
+            t.name,
+            if t.is_active(now) {
+                "active"
+            } else {
+                "inactive"
+            },
+            t.scopes
+        );
+    }
+    Ok(())
+}
@@ -37,6 +37,46 @@ pub fn timer() -> data::Timer {
     CONFIG.lock().unwrap().timer.clone()
 }
 
+pub fn price() -> data::Price {
+    CONFIG.lock().unwrap().price.clone()
+}
+
+pub fn market() -> data::Market {
+    CONFIG.lock().unwrap().market.clone()
+}
+
+pub fn fx() -> data::Fx {
+    CONFIG.lock().unwrap().fx.clone()
+}
+
+pub fn history() -> data::History {
+    CONFIG.lock().unwrap().history.clone()
+}
+
+pub fn alert() -> data::Alert {
+    CONFIG.lock().unwrap().alert.clone()
+}
+
+pub fn stream() -> data::Stream {
+    CONFIG.lock().unwrap().stream.clone()
+}
+
+pub fn scheduler() -> data::Scheduler {
+    CONFIG.lock().unwrap().scheduler.clone()
+}
+
+pub fn upstream() -> data::Upstream {
+    CONFIG.lock().unwrap().upstream.clone()
+}
+
+pub fn freshness() -> data::Freshness {
+    CONFIG.lock().unwrap().freshness.clone()
+}
+
+pub fn cors() -> data::Cors {
+    CONFIG.lock().unwrap().cors.clone()
+}
+
 pub fn db_path() -> PathBuf {
     CONFIG.lock().unwrap().db_path.clone()
 }
@@ -76,13 +116,23 @@ impl Config {
                     self.api_key = c.api_key;
                     self.auth_token = c.auth_token;
                     self.timer = c.timer;
+                    self.cors = c.cors;
+                    self.price = c.price;
+                    self.history = c.history;
+                    self.alert = c.alert;
+                    self.stream = c.stream;
+                    self.scheduler = c.scheduler;
+                    self.upstream = c.upstream;
+                    self.freshness = c.freshness;
+                    self.market = c.market;
+                    self.fx = c.fx;
                     Ok(())
                 }
-                Err(e) => Err(anyhow!("{:?}", e).into()),
+                Err(e) => Err(anyhow!("{:?}", e)),
             },
             Err(_) => match serde_json::to_string_pretty(self) {
                 Ok(text) => Ok(fs::write(&self.config_path, text)?),
-                Err(e) => Err(anyhow!("{:?}", e).into()),
+                Err(e) => Err(anyhow!("{:?}", e)),
             },
         }
     }
@@ -91,7 +141,7 @@ impl Config {
     pub fn save(&self) -> Result<()> {
         match serde_json::to_string_pretty(self) {
             Ok(text) => Ok(fs::write(&self.config_path, text)?),
-            Err(e) => Err(anyhow!("{:?}", e).into()),
+            Err(e) => Err(anyhow!("{:?}", e)),
         }
     }
 }
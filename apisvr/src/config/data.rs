@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Config {
@@ -14,6 +14,36 @@ pub struct Config {
     pub api_key: ApiKey,
     pub auth_token: AuthToken,
     pub timer: Timer,
+
+    #[serde(default)]
+    pub cors: Cors,
+
+    #[serde(default)]
+    pub price: Price,
+
+    #[serde(default)]
+    pub history: History,
+
+    #[serde(default)]
+    pub alert: Alert,
+
+    #[serde(default)]
+    pub stream: Stream,
+
+    #[serde(default)]
+    pub scheduler: Scheduler,
+
+    #[serde(default)]
+    pub upstream: Upstream,
+
+    #[serde(default)]
+    pub freshness: Freshness,
+
+    #[serde(default)]
+    pub market: Market,
+
+    #[serde(default)]
+    pub fx: Fx,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,6 +71,12 @@ pub struct Socket5 {
     pub blockstream: bool,
     pub ethscan: bool,
     pub awtmt: bool,
+
+    #[serde(default)]
+    pub coingecko: bool,
+
+    #[serde(default)]
+    pub exchangerate: bool,
 }
 
 impl Default for Socket5 {
@@ -53,6 +89,8 @@ impl Default for Socket5 {
             blockstream: false,
             ethscan: false,
             awtmt: false,
+            coingecko: false,
+            exchangerate: false,
         }
     }
 }
@@ -60,6 +98,10 @@ impl Default for Socket5 {
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct ApiKey {
     pub coinmarketcap: String,
+
+    // Optional, the public CoinGecko api works without a key.
+    #[serde(default)]
+    pub coingecko: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -77,8 +119,378 @@ impl Default for Timer {
     }
 }
 
+// `providers` are tried in order until one returns a valid listing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Price {
+    pub providers: Vec<String>,
+    pub limit: usize,
+}
+
+impl Default for Price {
+    fn default() -> Self {
+        Self {
+            providers: vec!["coinmarketcap".to_string(), "coingecko".to_string()],
+            limit: 100,
+        }
+    }
+}
+
+// awtmt product codes served by `/market/latest` and `/market/quote`. Outside
+// the sessions of every exchange the market is polled every
+// `closed_interval_secs` instead, 0 pauses polling until the next session.
+// Intraday samples are kept for `intraday_retention` seconds, daily closes for
+// good.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Market {
+    pub products: Vec<String>,
+    pub closed_interval_secs: u64,
+    pub intraday_retention: i64,
+    pub exchanges: BTreeMap<String, Exchange>,
+}
+
+// `sessions` are local `HH:MM-HH:MM` ranges in `timezone` on `weekdays`,
+// `holidays` are local `YYYY-MM-DD` dates. Products of no exchange are always
+// live.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Exchange {
+    pub timezone: String,
+    pub products: Vec<String>,
+    pub sessions: Vec<String>,
+    pub weekdays: Vec<String>,
+    pub holidays: Vec<String>,
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        Self {
+            timezone: "UTC".to_string(),
+            products: vec![],
+            sessions: vec![],
+            weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri"]
+                .map(String::from)
+                .to_vec(),
+            holidays: vec![],
+        }
+    }
+}
+
+impl Exchange {
+    fn new(timezone: &str, products: &[&str], sessions: &[&str]) -> Self {
+        Self {
+            timezone: timezone.to_string(),
+            products: products.iter().map(|v| v.to_string()).collect(),
+            sessions: sessions.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Market {
+    fn default() -> Self {
+        let china = ["09:30-11:30", "13:00-15:00"];
+        Self {
+            products: [
+                "000001.SS",
+                "DXY.OTC",
+                "US10YR.OTC",
+                "USDCNH.OTC",
+                "399001.SZ",
+                "399006.SZ",
+                "US500.OTC",
+            ]
+            .map(String::from)
+            .to_vec(),
+            closed_interval_secs: 1800,
+            intraday_retention: 7 * 24 * 3600,
+            exchanges: BTreeMap::from([
+                (
+                    "sse".to_string(),
+                    Exchange::new("Asia/Shanghai", &["000001.SS"], &china),
+                ),
+                (
+                    "szse".to_string(),
+                    Exchange::new("Asia/Shanghai", &["399001.SZ", "399006.SZ"], &china),
+                ),
+                (
+                    "us".to_string(),
+                    Exchange::new(
+                        "America/New_York",
+                        &["US500.OTC", "US10YR.OTC", "DXY.OTC"],
+                        &["09:30-16:00"],
+                    ),
+                ),
+                (
+                    "fx".to_string(),
+                    Exchange::new("America/New_York", &["USDCNH.OTC"], &["00:00-24:00"]),
+                ),
+            ]),
+        }
+    }
+}
+
+// Currencies `?currency=` converts USD values to. `market` maps a currency to
+// the awtmt product quoting it per US dollar. With `provider` on,
+// open.er-api.com fills in the others every `provider_interval_secs`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Fx {
+    pub market: BTreeMap<String, String>,
+    pub provider: bool,
+    pub provider_interval_secs: u64,
+}
+
+impl Default for Fx {
+    fn default() -> Self {
+        Self {
+            market: BTreeMap::from([("CNY".to_string(), "USDCNH.OTC".to_string())]),
+            provider: false,
+            provider_interval_secs: 3600,
+        }
+    }
+}
+
+// Price history is kept at every `resolution` for `retention` seconds, both
+// in seconds. Samples come with `timer.coinmarketcap_latest`, finer tiers are
+// ignored. The default keeps half hours for 30 days and hours for a year.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct History {
+    pub tiers: Vec<HistoryTier>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryTier {
+    pub resolution: i64,
+    pub retention: i64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            tiers: vec![
+                HistoryTier {
+                    resolution: 1800,
+                    retention: 30 * 24 * 3600,
+                },
+                HistoryTier {
+                    resolution: 3600,
+                    retention: 365 * 24 * 3600,
+                },
+            ],
+        }
+    }
+}
+
+// Webhook delivery, `retry_delay` doubles after every failed attempt. A
+// webhook that resolves to a loopback or private address is refused unless
+// its host is listed in `allowed_hosts`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Alert {
+    pub max_attempts: u32,
+    pub retry_delay_ms: u64,
+    pub timeout_secs: u64,
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for Alert {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_delay_ms: 5000,
+            timeout_secs: 10,
+            allowed_hosts: vec![],
+        }
+    }
+}
+
+// Requests to the upstream apis are retried on connection errors, 429 and
+// 5xx after `backoff_ms`, doubled every attempt up to `max_backoff_ms`. A
+// provider that failed `failure_threshold` times in a row is not called for
+// `open_secs`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Upstream {
+    pub connect_timeout_secs: u64,
+    pub timeout_secs: u64,
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub failure_threshold: u32,
+    pub open_secs: i64,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 5,
+            timeout_secs: 15,
+            max_attempts: 3,
+            backoff_ms: 500,
+            max_backoff_ms: 10000,
+            failure_threshold: 5,
+            open_secs: 60,
+        }
+    }
+}
+
+impl Upstream {
+    // The longest one request can take, every attempt timing out plus the
+    // backoff between them.
+    pub fn budget_secs(&self) -> u64 {
+        let attempts = self.max_attempts.max(1);
+        let backoff_ms = (1..attempts)
+            .map(|a| {
+                self.backoff_ms
+                    .saturating_mul(2_u64.saturating_pow(a - 1))
+                    .min(self.max_backoff_ms)
+            })
+            .sum::<u64>();
+        attempts as u64 * self.timeout_secs + backoff_ms.div_ceil(1000)
+    }
+}
+
+// Seconds after which a cached value is reported stale, keyed by cache
+// entry: `latest`, `greed_fear`, `global`, `eth_gas`, `btc_gas` and
+// `market`. Entries without a threshold use `default_secs`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Freshness {
+    pub default_secs: i64,
+    pub thresholds: BTreeMap<String, i64>,
+}
+
+impl Default for Freshness {
+    fn default() -> Self {
+        Self {
+            default_secs: 600,
+            thresholds: BTreeMap::from([
+                ("latest".to_string(), 3600),
+                ("greed_fear".to_string(), 300),
+                ("global".to_string(), 300),
+                ("eth_gas".to_string(), 180),
+                ("btc_gas".to_string(), 180),
+                ("market".to_string(), 120),
+                ("fx".to_string(), 7200),
+            ]),
+        }
+    }
+}
+
+impl Freshness {
+    pub fn threshold(&self, name: &str) -> i64 {
+        self.thresholds
+            .get(name)
+            .copied()
+            .unwrap_or(self.default_secs)
+    }
+}
+
+// Keyed by job name, e.g. `latest` or `awtmt`. Missing fields keep the job's
+// defaults, a `cron` expression with seconds, e.g. `0 */5 * * * *`, replaces
+// the interval.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct Scheduler {
+    pub jobs: BTreeMap<String, JobOverride>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct JobOverride {
+    pub interval_secs: Option<u64>,
+    pub cron: Option<String>,
+    pub jitter_secs: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    pub max_concurrency: Option<usize>,
+}
+
+// Server-sent events, `buffer_size` events are kept for `Last-Event-ID` resume.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Stream {
+    pub heartbeat_secs: u64,
+    pub buffer_size: usize,
+}
+
+impl Default for Stream {
+    fn default() -> Self {
+        Self {
+            heartbeat_secs: 15,
+            buffer_size: 256,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct AuthToken {
     pub rssbox_android: String,
     pub admin: String,
+
+    #[serde(default)]
+    pub musicbox_android: String,
+}
+
+// Origins are exact, e.g. `https://admin.example.com`, wildcard subdomains,
+// e.g. `https://*.example.com`, or `*`. A `*` origin never gets credentials.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Cors {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub max_age: u64,
+    pub allow_credentials: bool,
+
+    // Keyed by mount path, e.g. `/admin`. The longest matching mount wins
+    // and its fields replace the top level ones.
+    pub mounts: BTreeMap<String, CorsOverride>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: [
+                "Authorization",
+                "Content-Type",
+                "X-Request-Id",
+                "Last-Event-ID",
+                "If-None-Match",
+                "If-Modified-Since",
+            ]
+            .map(String::from)
+            .to_vec(),
+            expose_headers: [
+                "X-Request-Id",
+                "X-Created-At",
+                "X-Updated-At",
+                "ETag",
+                "Age",
+            ]
+            .map(String::from)
+            .to_vec(),
+            max_age: 86400,
+            allow_credentials: false,
+            mounts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct CorsOverride {
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    pub expose_headers: Option<Vec<String>>,
+    pub max_age: Option<u64>,
+    pub allow_credentials: Option<bool>,
 }
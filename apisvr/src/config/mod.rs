@@ -1,4 +1,5 @@
 pub mod conf;
 mod data;
 
-pub use conf::{auth_token, db_path};
+pub use conf::{auth_token, cors, db_path};
+pub use data::{Exchange, HistoryTier, JobOverride, Market, Upstream};
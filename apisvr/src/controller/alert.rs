@@ -0,0 +1,221 @@
+use super::json_data;
+use crate::{
+    db::alert::{self, AlertSpec, Metric},
+    middleware::auth::{scope, Scoped},
+    response::{alert::resolve_webhook, data},
+};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Outcome, Request},
+};
+use serde_json::json;
+
+const DEFAULT_DELIVERIES: u32 = 20;
+const MAX_DELIVERIES: u32 = 100;
+
+// The id of the stored token that owns the alerts of a request. The config
+// tokens and open mode are shared by every client, so they get a 403.
+pub struct AlertsToken(i64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AlertsToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.guard::<Scoped<scope::Alerts>>().await {
+            Outcome::Success(auth) => match auth.token_id {
+                Some(id) => Outcome::Success(AlertsToken(id)),
+                None => Outcome::Error((Status::Forbidden, ())),
+            },
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(s) => Outcome::Forward(s),
+        }
+    }
+}
+
+// Returns the spec ready to store, or the message for a 400.
+async fn parse_spec(input: &str) -> Result<AlertSpec, String> {
+    let mut spec: AlertSpec =
+        serde_json::from_str(input).map_err(|e| format!("invalid alert: {e}"))?;
+
+    if !spec.threshold.is_finite() || !spec.hysteresis.is_finite() || spec.hysteresis < 0.0 {
+        return Err("threshold must be a number and hysteresis not negative".to_string());
+    }
+
+    if spec.cooldown < 0 {
+        return Err("cooldown must not be negative".to_string());
+    }
+
+    match spec.metric {
+        Metric::Price => match spec.symbol.as_deref().map(str::trim) {
+            Some(symbol) if !symbol.is_empty() => spec.symbol = Some(symbol.to_uppercase()),
+            _ => return Err("a price alert needs a symbol".to_string()),
+        },
+        Metric::GreedFear => spec.symbol = None,
+    }
+
+    resolve_webhook(&spec.webhook).await?;
+    Ok(spec)
+}
+
+#[get("/")]
+pub async fn all(token: AlertsToken) -> data::Data {
+    match alert::select_all(token.0).await {
+        Ok(v) => json_data(&v),
+        Err(e) => e.into(),
+    }
+}
+
+// Price alerts are checked after every listing refresh, that is every
+// `timer.coinmarketcap_latest` seconds (30 minutes by default), and greed
+// fear alerts every minute. A crossing shorter than that can go unnoticed.
+#[post("/", format = "application/json", data = "<input>")]
+pub async fn create(token: AlertsToken, input: &str) -> data::Data {
+    match parse_spec(input).await {
+        Ok(spec) => match alert::create(token.0, &spec).await {
+            Ok(v) => json_data(&v),
+            Err(e) => e.into(),
+        },
+        Err(e) => data::Data::error(Status::BadRequest, e),
+    }
+}
+
+#[get("/<id>")]
+pub async fn get(token: AlertsToken, id: i64) -> data::Data {
+    match alert::select(token.0, id).await {
+        Ok(v) => json_data(&v),
+        Err(e) => e.into(),
+    }
+}
+
+#[put("/<id>", format = "application/json", data = "<input>")]
+pub async fn update(token: AlertsToken, id: i64, input: &str) -> data::Data {
+    match parse_spec(input).await {
+        Ok(spec) => match alert::update(token.0, id, &spec).await {
+            Ok(v) => json_data(&v),
+            Err(e) => e.into(),
+        },
+        Err(e) => data::Data::error(Status::BadRequest, e),
+    }
+}
+
+#[delete("/<id>")]
+pub async fn delete(token: AlertsToken, id: i64) -> data::Data {
+    match alert::delete(token.0, id).await {
+        Ok(_) => data::Data::default(),
+        Err(e) => e.into(),
+    }
+}
+
+#[get("/<id>/deliveries?<limit>")]
+pub async fn deliveries(token: AlertsToken, id: i64, limit: Option<u32>) -> data::Data {
+    if let Err(e) = alert::select(token.0, id).await {
+        return e.into();
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_DELIVERIES).clamp(1, MAX_DELIVERIES);
+    match alert::select_deliveries(id, limit).await {
+        Ok(v) => json_data(&json!({ "alert_id": id, "data": v })),
+        Err(e) => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config,
+        db::{self, token},
+        middleware::auth::Scope,
+    };
+    use rocket::{
+        http::{ContentType, Header},
+        local::asynchronous::Client,
+        tokio,
+    };
+
+    #[tokio::test]
+    async fn test_parse_spec() {
+        let spec = parse_spec(
+            r#"{"metric": "price", "symbol": " btc ", "op": "above", "threshold": 70000,
+                "webhook": "https://203.0.113.10/hook"}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(spec.symbol.as_deref(), Some("BTC"));
+        assert_eq!((spec.hysteresis, spec.cooldown), (0.0, 0));
+
+        let spec = parse_spec(
+            r#"{"metric": "greed_fear", "symbol": "BTC", "op": "below", "threshold": 20,
+                "webhook": "https://203.0.113.10/hook"}"#,
+        )
+        .await
+        .unwrap();
+        assert!(spec.symbol.is_none());
+
+        for input in [
+            r#"{"metric": "price", "op": "above", "threshold": 1, "webhook": "https://203.0.113.10"}"#,
+            r#"{"metric": "price", "symbol": "BTC", "op": "up", "threshold": 1, "webhook": "https://203.0.113.10"}"#,
+            r#"{"metric": "price", "symbol": "BTC", "op": "above", "threshold": 1, "webhook": "ftp://203.0.113.10"}"#,
+            r#"{"metric": "price", "symbol": "BTC", "op": "above", "threshold": 1, "hysteresis": -1,
+                "webhook": "https://203.0.113.10"}"#,
+            r#"{"metric": "price", "symbol": "BTC", "op": "above", "threshold": 1,
+                "webhook": "http://127.0.0.2:8080/hook"}"#,
+        ] {
+            assert!(parse_spec(input).await.is_err(), "{input}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alerts_per_token() -> anyhow::Result<()> {
+        let _mtx = db::TEST_MTX.lock().await;
+        let _ = std::fs::remove_file("/tmp/alert-route-test.db");
+        db::init("/tmp/alert-route-test.db").await;
+        config::conf::CONFIG.lock().unwrap().auth_token.admin = "admin-token".to_string();
+
+        let (_, first) = token::create("first", &[scope::Alerts::NAME], None).await?;
+        let (_, second) = token::create("second", &[scope::Alerts::NAME], None).await?;
+        let client =
+            Client::tracked(rocket::build().mount("/alerts", routes![all, create, get])).await?;
+        let bearer = |secret: &str| Header::new("Authorization", format!("Bearer {secret}"));
+
+        let resp = client
+            .post("/alerts")
+            .header(ContentType::JSON)
+            .header(bearer(&first))
+            .body(r#"{"metric": "greed_fear", "op": "below", "threshold": 20, "webhook": "https://203.0.113.10/hook"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let id = serde_json::from_str::<alert::Alert>(&resp.into_string().await.unwrap())?.id;
+
+        // The config tokens are shared, they own no alerts.
+        let resp = client
+            .get("/alerts")
+            .header(bearer("admin-token"))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        let resp = client
+            .get(format!("/alerts/{id}"))
+            .header(bearer(&second))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotFound);
+        let resp = client
+            .get("/alerts")
+            .header(bearer(&second))
+            .dispatch()
+            .await;
+        assert_eq!(resp.into_string().await.unwrap(), "[]");
+
+        let resp = client
+            .get(format!("/alerts/{id}"))
+            .header(bearer(&first))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use crate::response::data;
+use rocket::{http::Status, Request};
+
+#[catch(400)]
+pub fn bad_request() -> data::Data {
+    data::Data::error(Status::BadRequest, "malformed request")
+}
+
+#[catch(401)]
+pub fn unauthorized() -> data::Data {
+    data::Data::error(Status::Unauthorized, "missing or invalid token")
+}
+
+#[catch(403)]
+pub fn forbidden() -> data::Data {
+    data::Data::error(Status::Forbidden, "token lacks the required scope")
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> data::Data {
+    data::Data::error(
+        Status::NotFound,
+        format!("no route for {} {}", request.method(), request.uri().path()),
+    )
+}
+
+#[catch(413)]
+pub fn payload_too_large() -> data::Data {
+    data::Data::error(Status::PayloadTooLarge, "request body is too large")
+}
+
+#[catch(422)]
+pub fn unprocessable_entity() -> data::Data {
+    data::Data::error(
+        Status::UnprocessableEntity,
+        "request parameters or body could not be parsed",
+    )
+}
+
+#[catch(default)]
+pub fn default(status: Status, _: &Request) -> data::Data {
+    data::Data::error(status, status.reason_lossy())
+}
@@ -0,0 +1,143 @@
+use super::pagination::PageOrder;
+use crate::response::cryptocurrency::CoinSort;
+use rocket::form::{self, FromFormField, ValueField};
+
+const DEFAULT_COIN_LIMIT: u32 = 100;
+const MAX_COIN_LIMIT: u32 = 5000;
+const DEFAULT_INTERVAL: Interval = Interval(3600);
+pub const DEFAULT_CANDLES: i64 = 100;
+pub const MAX_CANDLES: i64 = 5000;
+
+// `symbols` and `fields` are comma separated lists, `currency` defaults to USD.
+#[derive(FromForm, Debug)]
+pub struct CoinQuery {
+    pub symbols: Option<String>,
+    #[field(default_with = Some(CoinSort::Rank))]
+    pub sort: CoinSort,
+    #[field(default_with = Some(PageOrder::Asc))]
+    pub order: PageOrder,
+    #[field(default_with = Some(DEFAULT_COIN_LIMIT), validate = range(1..=MAX_COIN_LIMIT as isize))]
+    pub limit: u32,
+    pub fields: Option<String>,
+    pub currency: Option<String>,
+}
+
+pub fn split_list(v: &Option<String>) -> Vec<&str> {
+    v.as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// A candle width, either seconds or a number with a unit, e.g. `5m`, `4h`, `1d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval(pub i64);
+
+impl Interval {
+    pub fn parse(v: &str) -> Option<Self> {
+        let v = v.trim();
+        let (n, unit) = match v.char_indices().last()? {
+            (i, c) if c.is_ascii_alphabetic() => (&v[..i], c),
+            _ => (v, 's'),
+        };
+
+        let unit = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => return None,
+        };
+
+        n.parse::<i64>()
+            .ok()
+            .filter(|n| *n > 0)
+            .and_then(|n| n.checked_mul(unit))
+            .map(Interval)
+    }
+}
+
+impl<'v> FromFormField<'v> for Interval {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Interval::parse(field.value)
+            .ok_or_else(|| form::Error::validation("invalid interval").into())
+    }
+}
+
+// `from` and `to` are unix timestamps in seconds.
+#[derive(FromForm, Debug)]
+pub struct HistoryQuery {
+    #[field(default_with = Some(DEFAULT_INTERVAL))]
+    pub interval: Interval,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl HistoryQuery {
+    // Returns `(from, to)` or the message for a 400, `from` aligned down to
+    // `interval`. Without `from` the last `DEFAULT_CANDLES` candles up to
+    // `to`, which defaults to `now`.
+    pub fn range(&self, now: i64) -> Result<(i64, i64), String> {
+        let interval = self.interval.0;
+        let to = self.to.unwrap_or(now);
+        let from = match self.from {
+            Some(v) => Some(v),
+            None => interval
+                .checked_mul(DEFAULT_CANDLES)
+                .and_then(|v| to.checked_sub(v)),
+        };
+
+        let candles = from
+            .and_then(|from| to.checked_sub(from))
+            .filter(|v| *v >= 0)
+            .and_then(|v| v.checked_div(interval));
+        let from = from.and_then(|from| from.checked_sub(from.rem_euclid(interval)));
+        match (from, candles) {
+            (Some(from), Some(n)) if n <= MAX_CANDLES => Ok((from, to)),
+            _ => Err(format!(
+                "`from` must not be after `to` and the range at most {MAX_CANDLES} candles"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        assert_eq!(Interval::parse("300"), Some(Interval(300)));
+        assert_eq!(Interval::parse("5m"), Some(Interval(300)));
+        assert_eq!(Interval::parse("4h"), Some(Interval(4 * 3600)));
+        assert_eq!(Interval::parse("1w"), Some(Interval(7 * 86400)));
+        assert_eq!(Interval::parse("0m"), None);
+        assert_eq!(Interval::parse("m"), None);
+        assert_eq!(Interval::parse("1y"), None);
+    }
+
+    #[test]
+    fn test_history_range() {
+        let query = |interval, from, to| HistoryQuery {
+            interval: Interval(interval),
+            from,
+            to,
+        };
+
+        assert_eq!(query(60, None, None).range(6000), Ok((0, 6000)));
+        assert_eq!(query(60, Some(100), Some(160)).range(0), Ok((60, 160)));
+        assert!(query(60, Some(170), Some(160)).range(0).is_err());
+        assert!(query(60, Some(0), None).range(60 * 5001).is_err());
+
+        // Overflows are a 400, not a panic.
+        assert!(query(60, None, Some(i64::MIN)).range(0).is_err());
+        assert!(query(i64::MAX, None, None).range(0).is_err());
+        assert!(query(60, Some(i64::MIN), Some(i64::MAX)).range(0).is_err());
+        assert!(query(60, Some(i64::MIN), Some(i64::MIN + 60))
+            .range(0)
+            .is_err());
+    }
+}
@@ -1,32 +1,368 @@
-use crate::response::cryptocurrency;
-use crate::response::data;
+use super::{
+    coin_query::{split_list, CoinQuery, HistoryQuery},
+    json_data,
+    pagination::PageOrder,
+    parse_currency, with_freshness, Converted,
+};
+use crate::response::cryptocurrency::{self, Holding, COIN_FIELDS, STATS_JOBS};
+use crate::response::{
+    cache, data,
+    history::{candles, tiers},
+    provider::Latest,
+};
+use chrono::Utc;
 use rocket::http::ContentType;
 use rocket::http::Status;
+use serde_json::json;
+use std::collections::HashSet;
 
-#[get("/cryptocurrency/latest")]
-pub async fn latest() -> data::Data {
-    if let Some(v) = cryptocurrency::latest_cache().await {
-        data::Data::new(v.as_bytes().to_vec(), ContentType::JSON)
-    } else {
-        match cryptocurrency::fetch_latest().await {
-            Ok(v) => data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
-            Err(e) => {
-                let mut d = data::Data::new(e.to_string().as_bytes().to_vec(), ContentType::Plain);
-                d.status = Status::InternalServerError;
-                d
+async fn latest_or_fetch() -> Result<(Latest, cache::Freshness), data::Data> {
+    match cryptocurrency::latest_cache().await {
+        Some(v) => Ok((v, cache::freshness("latest"))),
+        None => match cryptocurrency::fetch_latest().await {
+            Ok(v) => {
+                let freshness = cache::Freshness::now(&v.source);
+                Ok((v, freshness))
             }
+            Err(e) => Err(data::Data::upstream_error("price providers", &e)),
+        },
+    }
+}
+
+#[get("/cryptocurrency/latest?<query..>")]
+pub async fn latest(query: CoinQuery) -> data::Data {
+    let fields = split_list(&query.fields);
+    let unknown = fields
+        .iter()
+        .filter(|f| !COIN_FIELDS.contains(f))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return data::Data::error(Status::BadRequest, "unknown fields")
+            .with_details(json!({ "unknown_fields": unknown, "fields": COIN_FIELDS }));
+    }
+
+    let rate = match parse_currency(query.currency.as_deref()) {
+        Ok(v) => v,
+        Err(e) => return *e,
+    };
+
+    let (latest, freshness) = match latest_or_fetch().await {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let symbols = split_list(&query.symbols)
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut coins = cryptocurrency::select_coins(
+        &latest.coins,
+        &symbols,
+        query.sort,
+        matches!(query.order, PageOrder::Desc),
+        query.limit as usize,
+    );
+
+    if let Some(rate) = &rate {
+        cryptocurrency::convert_coins(&mut coins, rate);
+    }
+
+    let v = Converted::new(
+        cache::Fresh {
+            freshness,
+            data: cryptocurrency::project_coins(&coins, &fields),
+        },
+        rate,
+    );
+    let etag = v.etag_content(v.inner.etag_content());
+    with_freshness(json_data(&v), &v.inner.freshness, &["latest"]).with_etag(&etag)
+}
+
+// The CoinMarketCap listing layout served by `/cryptocurrency/latest` before
+// the typed model, kept for old app versions.
+#[get("/cryptocurrency/latest/raw")]
+pub async fn latest_raw() -> data::Data {
+    match latest_or_fetch().await {
+        Ok((latest, freshness)) => {
+            let v = cryptocurrency::listing(&latest).to_string();
+            with_freshness(
+                data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
+                &freshness,
+                &["latest"],
+            )
         }
+        Err(e) => e,
     }
 }
 
-#[get("/cryptocurrency/stats")]
-pub async fn greed_fear() -> data::Data {
+// Without `from` the last 100 candles up to `to`, which defaults to now.
+#[get("/cryptocurrency/<symbol>/history?<query..>")]
+pub async fn history(symbol: &str, query: HistoryQuery) -> data::Data {
+    let (from, to) = match query.range(Utc::now().timestamp()) {
+        Ok(v) => v,
+        Err(e) => return data::Data::error(Status::BadRequest, e),
+    };
+
+    match candles(symbol, query.interval.0, from, to).await {
+        Ok(Some(v)) => json_data(&v),
+        Ok(None) => data::Data::error(
+            Status::BadRequest,
+            "interval must be a multiple of a stored resolution",
+        )
+        .with_details(json!({
+            "resolutions": tiers().iter().map(|t| t.resolution).collect::<Vec<_>>()
+        })),
+        Err(e) => e.into(),
+    }
+}
+
+// Every field with its own `fetched_at`, `source`, `age_seconds` and `stale`,
+// the headers date the most recently refreshed one.
+#[get("/cryptocurrency/stats?<currency>")]
+pub async fn stats(currency: Option<&str>) -> data::Data {
+    let rate = match parse_currency(currency) {
+        Ok(v) => v,
+        Err(e) => return *e,
+    };
+
+    let stats = cryptocurrency::fresh_stats(rate.as_ref()).await;
+    let v = Converted::new(stats, rate);
+    let etag = v.etag_content(v.inner.etag_content());
+    with_freshness(json_data(&v), v.inner.newest(), STATS_JOBS).with_etag(&etag)
+}
+
+// The stats layout before the freshness fields, kept for old app versions.
+#[get("/cryptocurrency/stats/raw")]
+pub async fn stats_raw() -> data::Data {
+    let freshness = cryptocurrency::fresh_stats(None).await.newest().clone();
     match cryptocurrency::stats_cache().await {
-        Ok(v) => data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
+        Ok(v) => with_freshness(
+            data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
+            &freshness,
+            STATS_JOBS,
+        ),
         Err(e) => {
-            let mut d = data::Data::new(e.to_string().as_bytes().to_vec(), ContentType::Plain);
-            d.status = Status::InternalServerError;
-            d
+            log::warn!("serialize stats error: {e:?}");
+            data::Data::error(Status::InternalServerError, "internal server error")
+        }
+    }
+}
+
+// Symbols uppercased, each listed once with an amount and cost that are not
+// negative.
+fn parse_holdings(input: &str) -> Result<Vec<Holding>, String> {
+    let mut holdings: Vec<Holding> =
+        serde_json::from_str(input).map_err(|e| format!("invalid holdings: {e}"))?;
+    if holdings.is_empty() {
+        return Err("no holdings".to_string());
+    }
+
+    let mut symbols = HashSet::new();
+    for h in &mut holdings {
+        h.symbol = h.symbol.trim().to_uppercase();
+        if h.symbol.is_empty() {
+            return Err("a holding needs a symbol".to_string());
+        }
+
+        let valid = |v: f64| v.is_finite() && v >= 0.0;
+        if !valid(h.amount) || !h.cost_basis.is_none_or(valid) {
+            return Err(format!(
+                "amount and cost_basis of `{}` must not be negative",
+                h.symbol
+            ));
         }
+
+        if !symbols.insert(h.symbol.clone()) {
+            return Err(format!("`{}` is listed more than once", h.symbol));
+        }
+    }
+    Ok(holdings)
+}
+
+// Valued from the cached listing only, never from a fetch, `cost_basis` is in
+// the requested currency.
+#[post(
+    "/cryptocurrency/portfolio?<currency>",
+    format = "application/json",
+    data = "<input>"
+)]
+pub async fn portfolio(currency: Option<&str>, input: &str) -> data::Data {
+    let holdings = match parse_holdings(input) {
+        Ok(v) => v,
+        Err(e) => return data::Data::error(Status::BadRequest, e),
+    };
+
+    let rate = match parse_currency(currency) {
+        Ok(v) => v,
+        Err(e) => return *e,
+    };
+
+    let Some(latest) = cryptocurrency::latest_cache().await else {
+        return data::Data::error(Status::ServiceUnavailable, "no cached prices yet");
+    };
+
+    let v = Converted::new(
+        cache::Fresh {
+            freshness: cache::freshness("latest"),
+            data: cryptocurrency::value_portfolio(&latest.coins, &holdings, rate.as_ref()),
+        },
+        rate,
+    );
+    json_data(&v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db,
+        middleware::auth::{MountGuarded, SelfCheck},
+        response::fx,
+    };
+    use rocket::local::asynchronous::Client;
+    use serde_json::Value;
+
+    #[rocket::async_test]
+    async fn test_stats_freshness() {
+        let _mtx = db::TEST_MTX.lock().await;
+        let _ = std::fs::remove_file("/tmp/stats-freshness-test.db");
+        db::init("/tmp/stats-freshness-test.db").await;
+        cache::checked("eth_gas", "etherscan", Ok(25))
+            .await
+            .unwrap();
+
+        let client = Client::tracked(rocket::build().mount("/", routes![stats]))
+            .await
+            .unwrap();
+        let resp = client.get("/cryptocurrency/stats").dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert!(resp.headers().get_one("Age").is_some());
+        assert!(resp
+            .headers()
+            .get_one("Last-Modified")
+            .is_some_and(|v| v.ends_with(" GMT")));
+        let tag = resp.headers().get_one("ETag").unwrap().to_string();
+
+        let v: Value = serde_json::from_str(&resp.into_string().await.unwrap()).unwrap();
+        let eth = &v["gas_fee"]["ethereum"];
+        assert_eq!(
+            (&eth["source"], &eth["stale"]),
+            (&json!("etherscan"), &json!(false))
+        );
+        assert!(eth["age_seconds"].is_i64());
+        assert!(v["global"]["stale"].as_bool().unwrap());
+        assert!(v["greed_fear"]["data"].is_object());
+
+        // The tag leaves out `age_seconds`, so it holds until a refresh.
+        let resp = client
+            .get("/cryptocurrency/stats")
+            .header(rocket::http::Header::new("If-None-Match", tag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::NotModified);
+
+        cache::checked("global", "alternative", Ok(()))
+            .await
+            .unwrap();
+        let resp = client
+            .get("/cryptocurrency/stats")
+            .header(rocket::http::Header::new("If-None-Match", tag))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_portfolio_input() {
+        let holdings =
+            parse_holdings(r#"[{"symbol": " btc ", "amount": 0.5, "cost_basis": 100}, {"symbol": "eth", "amount": 2}]"#)
+                .unwrap();
+        assert_eq!(
+            (holdings[0].symbol.as_str(), holdings[0].cost_basis),
+            ("BTC", Some(100.0))
+        );
+        assert_eq!(
+            (holdings[1].symbol.as_str(), holdings[1].cost_basis),
+            ("ETH", None)
+        );
+
+        for input in [
+            "[]",
+            "{}",
+            r#"[{"symbol": "", "amount": 1}]"#,
+            r#"[{"symbol": "BTC", "amount": -1}]"#,
+            r#"[{"symbol": "BTC", "amount": 1, "cost_basis": -1}]"#,
+            r#"[{"symbol": "BTC", "amount": 1}, {"symbol": "btc", "amount": 2}]"#,
+        ] {
+            assert!(parse_holdings(input).is_err(), "{input}");
+        }
+
+        // Allowlisted as read only, so the check for public routes that change
+        // data lets it through.
+        let rocket = rocket::build()
+            .attach(SelfCheck)
+            .mount_guarded("/", crate::guarded![public => [portfolio]]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let resp = client
+            .post("/cryptocurrency/portfolio")
+            .header(ContentType::JSON)
+            .body("[]")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_stats_currency() {
+        let _mtx = fx::TEST_MTX.lock().await;
+        let client = Client::tracked(rocket::build().mount("/", routes![stats]))
+            .await
+            .unwrap();
+
+        let resp = client
+            .get("/cryptocurrency/stats?currency=XYZ")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Any currency code may come with the provider, until it answered.
+        crate::config::conf::CONFIG.lock().unwrap().fx.provider = true;
+        let pending = client
+            .get("/cryptocurrency/stats?currency=XYZ")
+            .dispatch()
+            .await;
+        let unknown = client
+            .get("/cryptocurrency/stats?currency=X1")
+            .dispatch()
+            .await;
+        crate::config::conf::CONFIG.lock().unwrap().fx.provider = false;
+        assert_eq!(pending.status(), Status::ServiceUnavailable);
+        assert_eq!(unknown.status(), Status::BadRequest);
+
+        fx::update(vec![fx::Rate {
+            currency: "TST".to_string(),
+            rate: 2.0,
+            source: "awtmt".to_string(),
+            fetched_at: 100,
+        }]);
+        let resp = client
+            .get("/cryptocurrency/stats?currency=tst")
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let v: Value = serde_json::from_str(&resp.into_string().await.unwrap()).unwrap();
+        assert_eq!(
+            (&v["currency"], &v["fx"]["rate"]),
+            (&json!("TST"), &json!(2.0))
+        );
+        assert_eq!(v["fx"]["fetched_at"], 100);
+        assert!(v["global"]["data"]["total_market_cap"].is_f64());
+
+        let resp = client.get("/cryptocurrency/stats").dispatch().await;
+        let v: Value = serde_json::from_str(&resp.into_string().await.unwrap()).unwrap();
+        assert_eq!(v["currency"], "USD");
+        assert!(v.get("fx").is_none());
+        assert!(v["global"]["data"].get("total_market_cap").is_none());
     }
 }
@@ -1,22 +1,23 @@
 use super::*;
+use crate::middleware::auth::{scope, Scoped};
 use crate::response::data;
 
 pub mod rssbox_android {
     use super::*;
     use crate::db::RSSBOX_ANDROID_FEEDBACK_TABLE;
 
-    #[get("/feedbacks")]
-    pub async fn all() -> data::Data {
-        com_all(RSSBOX_ANDROID_FEEDBACK_TABLE).await
+    #[get("/feedbacks?<page..>")]
+    pub async fn all(_auth: Scoped<scope::FeedbackRead>, page: Pagination) -> data::Data {
+        com_all(RSSBOX_ANDROID_FEEDBACK_TABLE, page).await
     }
 
     #[post("/feedback", format = "application/json", data = "<input>")]
-    pub async fn insert(input: &str) -> data::Data {
+    pub async fn insert(_auth: Scoped<scope::FeedbackRssbox>, input: &str) -> data::Data {
         com_insert(RSSBOX_ANDROID_FEEDBACK_TABLE, input).await
     }
 
     #[delete("/feedback/<uuid>")]
-    pub async fn delete(uuid: &str) -> data::Data {
+    pub async fn delete(_auth: Scoped<scope::FeedbackDelete>, uuid: &str) -> data::Data {
         com_delete(RSSBOX_ANDROID_FEEDBACK_TABLE, uuid).await
     }
 }
@@ -25,18 +26,18 @@ pub mod musicbox_android {
     use super::*;
     use crate::db::MUSICBOX_ANDROID_FEEDBACK_TABLE;
 
-    #[get("/feedbacks")]
-    pub async fn all() -> data::Data {
-        com_all(MUSICBOX_ANDROID_FEEDBACK_TABLE).await
+    #[get("/feedbacks?<page..>")]
+    pub async fn all(_auth: Scoped<scope::FeedbackRead>, page: Pagination) -> data::Data {
+        com_all(MUSICBOX_ANDROID_FEEDBACK_TABLE, page).await
     }
 
     #[post("/feedback", format = "application/json", data = "<input>")]
-    pub async fn insert(input: &str) -> data::Data {
+    pub async fn insert(_auth: Scoped<scope::FeedbackMusicbox>, input: &str) -> data::Data {
         com_insert(MUSICBOX_ANDROID_FEEDBACK_TABLE, input).await
     }
 
     #[delete("/feedback/<uuid>")]
-    pub async fn delete(uuid: &str) -> data::Data {
+    pub async fn delete(_auth: Scoped<scope::FeedbackDelete>, uuid: &str) -> data::Data {
         com_delete(MUSICBOX_ANDROID_FEEDBACK_TABLE, uuid).await
     }
 }
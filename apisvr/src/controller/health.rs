@@ -0,0 +1,72 @@
+use super::json_data;
+use crate::{
+    middleware::auth::{scope, Scoped},
+    response::{
+        cache, data,
+        upstream::{self, State},
+    },
+};
+use serde_json::{json, Value};
+
+// `degraded` while any upstream circuit breaker is not closed or the last
+// refresh of a cache failed. The error texts can name upstream hosts and
+// keys, they are only reported to an admin.
+fn report(with_errors: bool) -> Value {
+    let mut upstreams = upstream::breakers();
+    let mut caches = cache::statuses();
+    let is_ok = upstreams.iter().all(|b| b.state == State::Closed)
+        && caches.iter().all(|c| c.consecutive_failures == 0);
+
+    if !with_errors {
+        upstreams.iter_mut().for_each(|b| b.last_error = None);
+        caches.iter_mut().for_each(|c| c.error = None);
+    }
+
+    json!({
+        "status": if is_ok { "ok" } else { "degraded" },
+        "upstreams": upstreams,
+        "caches": caches,
+    })
+}
+
+#[get("/health")]
+pub fn health() -> data::Data {
+    json_data(&report(false))
+}
+
+// `/health` with the last error of every upstream and cache.
+#[get("/health")]
+pub fn errors(_auth: Scoped<scope::Admin>) -> data::Data {
+    json_data(&report(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use rocket::tokio;
+
+    #[tokio::test]
+    async fn test_report_errors() {
+        let result = Err::<(), _>(anyhow!("http://10.0.0.1/?key=secret"));
+        assert!(cache::checked("test-health", "mock", result).await.is_err());
+
+        let cache = |v: &Value| {
+            v["caches"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["name"] == "test-health")
+                .cloned()
+                .unwrap()
+        };
+
+        let public = report(false);
+        assert_eq!(public["status"], "degraded");
+        assert_eq!(cache(&public)["consecutive_failures"], 1);
+        assert!(!public.to_string().contains("key=secret"));
+
+        let admin = report(true);
+        assert_eq!(cache(&admin)["error"], "http://10.0.0.1/?key=secret");
+    }
+}
@@ -0,0 +1,11 @@
+use super::json_data;
+use crate::{
+    middleware::auth::{scope, Scoped},
+    response::data,
+    scheduler,
+};
+
+#[get("/jobs")]
+pub fn all(_auth: Scoped<scope::Admin>) -> data::Data {
+    json_data(&scheduler::statuses())
+}
@@ -1,18 +1,152 @@
-use crate::response::{data, market};
-use rocket::http::{ContentType, Status};
+use super::{
+    coin_query::{split_list, HistoryQuery},
+    json_data, with_freshness,
+};
+use crate::{
+    conf,
+    response::{
+        cache::{Fresh, Freshness},
+        data,
+        market::{self, MarketData, Quote},
+        market_history::candles,
+    },
+};
+use chrono::Utc;
+use rocket::http::Status;
+use serde_json::json;
+
+async fn quotes_or_fetch() -> Result<(Vec<Quote>, Freshness), data::Data> {
+    match market::quotes_cache().await {
+        Some(v) => Ok((v, market::freshness())),
+        None => match market::fetch().await {
+            Ok(v) => Ok((v, Freshness::now("awtmt"))),
+            Err(e) => Err(data::Data::upstream_error("awtmt", &e)),
+        },
+    }
+}
 
 #[get("/market/latest")]
 pub async fn latest() -> data::Data {
-    if let Some(v) = market::latest_cache().await {
-        data::Data::new(v.as_bytes().to_vec(), ContentType::JSON)
-    } else {
-        match market::fetch().await {
-            Ok(v) => data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
-            Err(e) => {
-                let mut d = data::Data::new(e.to_string().as_bytes().to_vec(), ContentType::Plain);
-                d.status = Status::InternalServerError;
-                d
-            }
+    match quotes_or_fetch().await {
+        Ok((quotes, freshness)) => {
+            let v = Fresh {
+                freshness,
+                data: market::by_code(&quotes)
+                    .into_iter()
+                    .map(|q| market::with_session(&q.code, MarketData::from(q)))
+                    .collect::<Vec<_>>(),
+            };
+            with_freshness(json_data(&v), &v.freshness, &["awtmt"]).with_etag(&v.etag_content())
+        }
+        Err(e) => e,
+    }
+}
+
+// The bare quote list served by `/market/latest` before the freshness fields,
+// kept for old app versions.
+#[get("/market/latest/raw")]
+pub async fn latest_raw() -> data::Data {
+    match quotes_or_fetch().await {
+        Ok((quotes, freshness)) => {
+            with_freshness(json_data(&market::legacy(&quotes)), &freshness, &["awtmt"])
         }
+        Err(e) => e,
+    }
+}
+
+// The configured products among `codes` in their order, every configured
+// product when `codes` is missing or empty.
+fn parse_codes(codes: Option<&str>, products: &[String]) -> Result<Vec<String>, Box<data::Data>> {
+    let codes = codes.map(String::from);
+    let codes = split_list(&codes);
+    if codes.is_empty() {
+        return Ok(products.to_vec());
+    }
+
+    let find = |code: &str| products.iter().find(|p| p.eq_ignore_ascii_case(code));
+    let unknown = codes
+        .iter()
+        .filter(|c| find(c).is_none())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(Box::new(
+            data::Data::error(Status::BadRequest, "unknown codes")
+                .with_details(json!({ "unknown_codes": unknown, "codes": products })),
+        ));
+    }
+
+    Ok(codes.into_iter().filter_map(find).cloned().collect())
+}
+
+#[get("/market/quote?<codes>")]
+pub async fn quote(codes: Option<&str>) -> data::Data {
+    let codes = match parse_codes(codes, &conf::market().products) {
+        Ok(v) => v,
+        Err(e) => return *e,
+    };
+
+    let (quotes, freshness) = match quotes_or_fetch().await {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let v = Fresh {
+        freshness,
+        data: codes
+            .iter()
+            .filter_map(|c| quotes.iter().find(|q| &q.code == c))
+            .map(|q| market::with_session(&q.code, q))
+            .collect::<Vec<_>>(),
+    };
+    with_freshness(json_data(&v), &v.freshness, &["awtmt"]).with_etag(&v.etag_content())
+}
+
+// Whole-day intervals are served from the daily closes, shorter ones from the
+// intraday samples of the last `market.intraday_retention` seconds.
+#[get("/market/<code>/history?<query..>")]
+pub async fn history(code: &str, query: HistoryQuery) -> data::Data {
+    let code = match parse_codes(Some(code), &conf::market().products) {
+        Ok(mut v) => v.remove(0),
+        Err(e) => return *e,
+    };
+
+    let (from, to) = match query.range(Utc::now().timestamp()) {
+        Ok(v) => v,
+        Err(e) => return data::Data::error(Status::BadRequest, e),
+    };
+
+    match candles(&code, query.interval.0, from, to).await {
+        Ok(v) => json_data(&v),
+        Err(e) => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_parse_codes() {
+        let products = conf::market().products;
+        assert_eq!(parse_codes(None, &products).ok().unwrap(), products);
+        assert_eq!(
+            parse_codes(Some("usdcnh.otc, 000001.SS"), &products)
+                .ok()
+                .unwrap(),
+            vec!["USDCNH.OTC", "000001.SS"]
+        );
+        assert!(parse_codes(Some("DXY.OTC,AAPL.US"), &products).is_err());
+    }
+
+    #[test]
+    fn test_history_range() {
+        let client = Client::tracked(rocket::build().mount("/", routes![history])).unwrap();
+        let uri = format!(
+            "/market/000001.SS/history?from={}&to={}&interval=1m",
+            i64::MIN,
+            i64::MIN + 60
+        );
+        assert_eq!(client.get(uri).dispatch().status(), Status::BadRequest);
     }
 }
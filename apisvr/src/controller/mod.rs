@@ -1,63 +1,141 @@
+pub mod alert;
 pub mod backup_recover;
+pub mod catcher;
+pub mod coin_query;
 pub mod cryptocurrency;
 pub mod feedback;
+pub mod health;
+pub mod job;
 pub mod market;
+pub mod pagination;
 pub mod ping;
 pub mod rss;
+pub mod stream;
+pub mod token;
 pub mod versions;
 
-use crate::{db::entry, response::data};
-use anyhow::Result;
+use crate::{
+    db::{self, entry},
+    response::{
+        cache::Freshness,
+        data,
+        fx::{self, Rate},
+    },
+    scheduler,
+};
+use pagination::Pagination;
 use rocket::http::{ContentType, Status};
+use serde::Serialize;
+use serde_json::json;
 use uuid::Uuid;
 
-async fn _all(table: &str) -> Result<String> {
-    let entrys = entry::select_all(table).await?;
-    Ok(serde_json::to_string(&entrys)?)
+fn json_data<T: Serialize>(v: &T) -> data::Data {
+    match serde_json::to_string(v) {
+        Ok(v) => data::Data::new(v.as_bytes().to_vec(), ContentType::JSON),
+        Err(e) => {
+            log::warn!("serialize response error: {e:?}");
+            data::Data::error(Status::InternalServerError, "internal server error")
+        }
+    }
+}
+
+// A cached payload may be cached by clients until the next refresh of the
+// jobs behind it, it is revalidated by its `fetched_at`.
+fn with_freshness(d: data::Data, f: &Freshness, jobs: &[&str]) -> data::Data {
+    let max_age = scheduler::interval_secs(jobs).unwrap_or_default();
+    let d = d.with_cache(max_age, f.fetched_at);
+    match f.age_seconds {
+        Some(age) => d.with_header("Age", age.to_string()),
+        None => d,
+    }
+}
+
+// A payload with its values in `currency`, converted at the `fx` rate.
+#[derive(Serialize, Debug)]
+struct Converted<T> {
+    #[serde(flatten)]
+    inner: T,
+    currency: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fx: Option<Rate>,
+}
+
+impl<T> Converted<T> {
+    fn new(inner: T, fx: Option<Rate>) -> Self {
+        Self {
+            inner,
+            currency: fx
+                .as_ref()
+                .map_or(fx::BASE.to_string(), |r| r.currency.clone()),
+            fx,
+        }
+    }
+
+    // The ETag content of `inner` with the rate it was converted at.
+    fn etag_content(&self, inner: Vec<u8>) -> Vec<u8> {
+        let rate = serde_json::to_vec(&self.fx).unwrap_or_default();
+        [inner, rate].concat()
+    }
+}
+
+// The rate of `?currency=`, None for US dollars. A supported currency whose
+// rate has not been fetched yet is unavailable rather than unknown.
+fn parse_currency(currency: Option<&str>) -> Result<Option<Rate>, Box<data::Data>> {
+    let Some(currency) = currency
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && !c.eq_ignore_ascii_case(fx::BASE))
+    else {
+        return Ok(None);
+    };
+
+    let currency = currency.to_uppercase();
+    if let Some(rate) = fx::rate(&currency) {
+        return Ok(Some(rate));
+    }
+
+    let e = if fx::is_pending(&currency) {
+        data::Data::error(
+            Status::ServiceUnavailable,
+            format!("no exchange rate for {currency} yet"),
+        )
+    } else {
+        data::Data::error(Status::BadRequest, "unknown currency")
+    };
+    Err(Box::new(e.with_details(
+        json!({ "currency": currency, "currencies": fx::currencies() }),
+    )))
 }
 
-async fn com_all(table: &str) -> data::Data {
-    match _all(table).await {
-        Ok(entrys) => data::Data::new(entrys.as_bytes().to_vec(), ContentType::JSON),
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
+async fn com_all(table: &str, page: Pagination) -> data::Data {
+    match entry::select_page(table, page.cursor, page.limit, page.order()).await {
+        Ok(page) => json_data(&page),
+        Err(e) => e.into(),
     }
 }
 
 async fn com_insert(table: &str, input: &str) -> data::Data {
     match entry::insert(table, &Uuid::new_v4().to_string(), input).await {
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
+        Err(e) => e.into(),
         _ => data::Data::default(),
     }
 }
 
 async fn com_delete(table: &str, uuid: &str) -> data::Data {
     match entry::delete(table, uuid).await {
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
+        Err(e) => e.into(),
         _ => data::Data::default(),
     }
 }
 
+// The stored JSON as is, clients parse it directly. The timestamps go in the
+// `X-Created-At`/`X-Updated-At` headers, see the README.
 async fn com_select(table: &str, uuid: &str) -> data::Data {
     match entry::select(table, uuid).await {
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
+        Err(e) => e.into(),
         Ok(v) => {
             data::Data::new_with_status(v.data.as_bytes().to_vec(), ContentType::JSON, Status::Ok)
+                .with_header("X-Created-At", v.created_at.to_string())
+                .with_header("X-Updated-At", v.updated_at.to_string())
         }
     }
 }
@@ -65,25 +143,12 @@ async fn com_select(table: &str, uuid: &str) -> data::Data {
 #[allow(dead_code)]
 async fn com_select_with_uuid(table: &str, uuid: &str) -> data::Data {
     match entry::select(table, uuid).await {
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
-        Ok(v) => match serde_json::to_string(&v) {
-            Err(e) => data::Data::new_with_status(
-                e.to_string().as_bytes().to_vec(),
-                ContentType::Plain,
-                Status::InternalServerError,
-            ),
-            Ok(v) => {
-                data::Data::new_with_status(v.as_bytes().to_vec(), ContentType::JSON, Status::Ok)
-            }
-        },
+        Err(e) => e.into(),
+        Ok(v) => json_data(&v),
     }
 }
 
-async fn _com_update(table: &str, uuid: &str, data: &str) -> Result<()> {
+async fn _com_update(table: &str, uuid: &str, data: &str) -> db::Result<()> {
     match entry::is_exist(table, uuid).await {
         true => entry::update(table, uuid, data).await?,
         false => entry::insert(table, uuid, data).await?,
@@ -94,11 +159,7 @@ async fn _com_update(table: &str, uuid: &str, data: &str) -> Result<()> {
 
 async fn com_update(table: &str, uuid: &str, data: &str) -> data::Data {
     match _com_update(table, uuid, data).await {
-        Err(e) => data::Data::new_with_status(
-            e.to_string().as_bytes().to_vec(),
-            ContentType::Plain,
-            Status::InternalServerError,
-        ),
+        Err(e) => e.into(),
         _ => data::Data::default(),
     }
 }
@@ -0,0 +1,28 @@
+use crate::db::entry;
+
+const DEFAULT_PAGE_LIMIT: u32 = 100;
+const MAX_PAGE_LIMIT: u32 = 1000;
+
+#[derive(FromFormField, Debug, Clone, Copy)]
+pub enum PageOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(FromForm, Debug)]
+pub struct Pagination {
+    #[field(default_with = Some(DEFAULT_PAGE_LIMIT), validate = range(1..=MAX_PAGE_LIMIT as isize))]
+    pub limit: u32,
+    pub cursor: Option<i64>,
+    #[field(default_with = Some(PageOrder::Asc))]
+    order: PageOrder,
+}
+
+impl Pagination {
+    pub fn order(&self) -> entry::Order {
+        match self.order {
+            PageOrder::Asc => entry::Order::Asc,
+            PageOrder::Desc => entry::Order::Desc,
+        }
+    }
+}
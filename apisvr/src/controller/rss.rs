@@ -1,8 +1,29 @@
 use super::*;
 use crate::{
-    db::{RSSBOX_ANDROID_RSS_CN_TABLE, RSSBOX_ANDROID_RSS_EN_TABLE},
+    db::{
+        entry::{self, InsertMode},
+        ComEntry, RSSBOX_ANDROID_RSS_CN_TABLE, RSSBOX_ANDROID_RSS_EN_TABLE,
+    },
+    middleware::auth::{scope, Scoped},
     response::data,
 };
+use rocket::data::{Data, Limits, ToByteUnit};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(FromFormField, Debug, Clone, Copy)]
+pub enum BulkMode {
+    Atomic,
+    Upsert,
+}
+
+// `data` may be the stored string itself, as returned by the list route,
+// or any other json value which is stored serialized.
+#[derive(Deserialize, Debug)]
+struct BulkItem {
+    uuid: Option<String>,
+    data: Value,
+}
 
 macro_rules! table_name {
     ($language: expr) => {
@@ -14,17 +35,72 @@ macro_rules! table_name {
     };
 }
 
-#[get("/<language>")]
-pub async fn all(language: &str) -> data::Data {
-    com_all(table_name!(language)).await
+#[get("/<language>?<page..>")]
+pub async fn all(language: &str, page: Pagination) -> data::Data {
+    com_all(table_name!(language), page).await
 }
 
 #[post("/<language>", format = "application/json", data = "<input>")]
-pub async fn insert(language: &str, input: &str) -> data::Data {
+pub async fn insert(_auth: Scoped<scope::RssWrite>, language: &str, input: &str) -> data::Data {
     com_insert(table_name!(language), input).await
 }
 
 #[delete("/<language>/<uuid>")]
-pub async fn delete(language: &str, uuid: &str) -> data::Data {
+pub async fn delete(_auth: Scoped<scope::RssAdmin>, language: &str, uuid: &str) -> data::Data {
     com_delete(table_name!(language), uuid).await
 }
+
+#[post(
+    "/bulk/<language>?<mode>",
+    format = "application/json",
+    data = "<input>"
+)]
+pub async fn bulk_insert(
+    _auth: Scoped<scope::RssAdmin>,
+    language: &str,
+    mode: Option<BulkMode>,
+    input: Data<'_>,
+    limits: &Limits,
+) -> data::Data {
+    let limit = limits.get("bulk").unwrap_or(16.mebibytes());
+    let input = match input.open(limit).into_string().await {
+        Ok(v) if v.is_complete() => v.into_inner(),
+        Ok(_) => return data::Data::error(Status::PayloadTooLarge, "request body is too large"),
+        Err(e) => {
+            log::warn!("read bulk insert body error: {e:?}");
+            return data::Data::error(Status::BadRequest, "failed to read request body");
+        }
+    };
+
+    let items = match serde_json::from_str::<Vec<BulkItem>>(&input) {
+        Ok(v) => v,
+        Err(e) => {
+            return data::Data::error(Status::BadRequest, "request body is not a list of entries")
+                .with_details(serde_json::json!({ "reason": e.to_string() }))
+        }
+    };
+
+    let entrys = items
+        .into_iter()
+        .map(|item| ComEntry {
+            uuid: item.uuid.unwrap_or_else(|| Uuid::new_v4().to_string()),
+            data: match item.data {
+                Value::String(v) => v,
+                v => v.to_string(),
+            },
+            ..Default::default()
+        })
+        .collect();
+
+    let mode = match mode {
+        Some(BulkMode::Upsert) => InsertMode::Upsert,
+        _ => InsertMode::Atomic,
+    };
+
+    match entry::insert_all(table_name!(language), entrys, mode).await {
+        Ok(report) if report.committed => json_data(&report),
+        Ok(report) => data::Data::error(Status::Conflict, "batch rejected, nothing was inserted")
+            .with_details(serde_json::to_value(&report).unwrap_or_default()),
+        Err(e) => e.into(),
+    }
+}
@@ -0,0 +1,146 @@
+use super::coin_query::split_list;
+use crate::{
+    conf,
+    response::{
+        data,
+        stream::{subscribe, Message, Topic},
+    },
+};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Outcome, Request},
+    response::stream::{Event, EventStream},
+    tokio::{select, sync::broadcast::error::RecvError, time::Duration},
+    Shutdown,
+};
+use serde_json::json;
+
+pub struct LastEventId(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|v| v.trim().parse().ok());
+        Outcome::Success(LastEventId(id))
+    }
+}
+
+// Every topic when `topics` is missing or empty.
+fn parse_topics(topics: Option<&str>) -> Result<Vec<Topic>, Box<data::Data>> {
+    let topics = topics.map(String::from);
+    let names = split_list(&topics);
+    if names.is_empty() {
+        return Ok(Topic::ALL.to_vec());
+    }
+
+    let unknown = names
+        .iter()
+        .filter(|n| Topic::parse(n).is_none())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        let all = Topic::ALL.map(Topic::name);
+        return Err(Box::new(
+            data::Data::error(Status::BadRequest, "unknown topics")
+                .with_details(json!({ "unknown_topics": unknown, "topics": all })),
+        ));
+    }
+
+    Ok(names.into_iter().filter_map(Topic::parse).collect())
+}
+
+fn event(msg: Message) -> Event {
+    Event::data(msg.data)
+        .event(msg.topic.name())
+        .id(msg.id.to_string())
+}
+
+// A lagging client is disconnected, it resumes from the ring buffer with
+// `Last-Event-ID` on reconnect.
+#[get("/stream?<topics>")]
+pub fn stream(
+    topics: Option<&str>,
+    last_event_id: LastEventId,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], Box<data::Data>> {
+    let topics = parse_topics(topics)?;
+    let (backlog, mut rx) = subscribe(last_event_id.0);
+    let heartbeat = Duration::from_secs(conf::stream().heartbeat_secs.max(1));
+
+    Ok(EventStream! {
+        for msg in backlog {
+            if topics.contains(&msg.topic) {
+                yield event(msg);
+            }
+        }
+
+        loop {
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed | RecvError::Lagged(_)) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if topics.contains(&msg.topic) {
+                yield event(msg);
+            }
+        }
+    }
+    .heartbeat(heartbeat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::stream::publish;
+    use rocket::{
+        local::asynchronous::Client,
+        tokio::{io::AsyncReadExt, time::timeout},
+    };
+
+    #[test]
+    fn test_parse_topics() {
+        assert_eq!(parse_topics(None).ok().unwrap(), Topic::ALL.to_vec());
+        assert_eq!(
+            parse_topics(Some("latest, market")).ok().unwrap(),
+            vec![Topic::Latest, Topic::Market]
+        );
+        assert!(parse_topics(Some("market,prices")).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn test_stream() {
+        let client = Client::tracked(rocket::build().mount("/", routes![stream]))
+            .await
+            .unwrap();
+
+        let resp = client.get("/stream?topics=foo").dispatch().await;
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        publish(Topic::Stats, "stream-test-stats".to_string());
+        publish(Topic::Market, "stream-test-market".to_string());
+
+        let mut resp = client.get("/stream?topics=market").dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+
+        let mut body = String::new();
+        let mut buf = [0_u8; 1024];
+        while !body.contains("stream-test-market") {
+            let n = timeout(Duration::from_secs(5), resp.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            body.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+
+        assert!(body.contains("event:market"));
+        assert!(!body.contains("stream-test-stats"));
+    }
+}
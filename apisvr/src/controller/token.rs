@@ -0,0 +1,84 @@
+use super::*;
+use crate::{
+    db::token,
+    middleware::auth::{scope, Scoped},
+    response::data,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize, Debug)]
+struct NewToken {
+    name: String,
+    scopes: Vec<String>,
+
+    // Seconds from now, the token never expires when it is missing.
+    expires_in: Option<i64>,
+}
+
+pub fn unknown_scopes<'a>(scopes: &[&'a str]) -> Vec<&'a str> {
+    scopes
+        .iter()
+        .filter(|s| !scope::ALL.contains(s))
+        .copied()
+        .collect()
+}
+
+#[post("/tokens", format = "application/json", data = "<input>")]
+pub async fn create(auth: Scoped<scope::TokensAdmin>, input: &str) -> data::Data {
+    let input: NewToken = match serde_json::from_str(input) {
+        Ok(v) => v,
+        Err(e) => return data::Data::error(Status::BadRequest, format!("invalid token: {e}")),
+    };
+
+    let scopes = input.scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let unknown = unknown_scopes(&scopes);
+    if input.name.trim().is_empty() || scopes.is_empty() || !unknown.is_empty() {
+        return data::Data::error(
+            Status::BadRequest,
+            "a token needs a name and at least one known scope",
+        )
+        .with_details(json!({ "unknown_scopes": unknown, "scopes": scope::ALL }));
+    }
+
+    let expires_at = input.expires_in.map(|v| Utc::now().timestamp() + v);
+    match token::create(input.name.trim(), &scopes, expires_at).await {
+        Ok((token, secret)) => {
+            log::info!("{} created api token {}", auth.owner, token.name);
+            json_data(&json!({ "token": token, "secret": secret }))
+        }
+        Err(e) => e.into(),
+    }
+}
+
+#[get("/tokens")]
+pub async fn all(_auth: Scoped<scope::TokensAdmin>) -> data::Data {
+    match token::select_all().await {
+        Ok(tokens) => json_data(&tokens),
+        Err(e) => e.into(),
+    }
+}
+
+#[delete("/tokens/<id>")]
+pub async fn revoke(auth: Scoped<scope::TokensAdmin>, id: i64) -> data::Data {
+    match token::revoke(id).await {
+        Ok(token) => {
+            log::info!("{} revoked api token {}", auth.owner, token.name);
+            json_data(&token)
+        }
+        Err(e) => e.into(),
+    }
+}
+
+// A new secret for the same token, the old one is refused from now on.
+#[post("/tokens/<id>/rotate")]
+pub async fn rotate(auth: Scoped<scope::TokensAdmin>, id: i64) -> data::Data {
+    match token::rotate(id).await {
+        Ok((token, secret)) => {
+            log::info!("{} rotated api token {}", auth.owner, token.name);
+            json_data(&json!({ "token": token, "secret": secret }))
+        }
+        Err(e) => e.into(),
+    }
+}
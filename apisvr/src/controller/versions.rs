@@ -2,9 +2,10 @@ use super::*;
 use crate::response::data;
 
 use crate::db::VERSIONS_TABLE;
+use crate::middleware::auth::{scope, Scoped};
 
 #[post("/latest/version?<q>", format = "application/json", data = "<input>")]
-pub async fn update(q: &str, input: &str) -> data::Data {
+pub async fn update(_auth: Scoped<scope::VersionsWrite>, q: &str, input: &str) -> data::Data {
     com_update(VERSIONS_TABLE, q, input).await
 }
 
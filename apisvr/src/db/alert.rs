@@ -0,0 +1,357 @@
+use super::{pool, Error, Result, ALERTS_TABLE, ALERT_DELIVERIES_TABLE};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Metric {
+    // USD price of `symbol`.
+    Price,
+    GreedFear,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Op {
+    Above,
+    Below,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+// The fields the owning token chooses, the rest is evaluation state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertSpec {
+    pub metric: Metric,
+
+    #[serde(default)]
+    pub symbol: Option<String>,
+    pub op: Op,
+    pub threshold: f64,
+
+    // How far the value has to move back across the threshold before the
+    // alert can fire again.
+    #[serde(default)]
+    pub hysteresis: f64,
+
+    // Minimum seconds between two triggers.
+    #[serde(default)]
+    pub cooldown: i64,
+    pub webhook: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Alert {
+    pub id: i64,
+    pub token_id: i64,
+    pub metric: Metric,
+    pub symbol: Option<String>,
+    pub op: Op,
+    pub threshold: f64,
+    pub hysteresis: f64,
+    pub cooldown: i64,
+    pub webhook: String,
+
+    // An alert fires only while armed, it re-arms past the hysteresis band.
+    pub armed: bool,
+    pub last_triggered_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    pub alert_id: i64,
+    pub triggered_at: i64,
+    pub value: f64,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub response_code: Option<i64>,
+    pub error: Option<String>,
+    pub finished_at: Option<i64>,
+}
+
+pub async fn create(token_id: i64, spec: &AlertSpec) -> Result<Alert> {
+    let now = Utc::now().timestamp();
+    let id = sqlx::query(&format!(
+        "INSERT INTO {} (token_id, metric, symbol, op, threshold, hysteresis, cooldown, webhook,
+         armed, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 1, ?, ?)",
+        ALERTS_TABLE
+    ))
+    .bind(token_id)
+    .bind(spec.metric)
+    .bind(&spec.symbol)
+    .bind(spec.op)
+    .bind(spec.threshold)
+    .bind(spec.hysteresis)
+    .bind(spec.cooldown)
+    .bind(&spec.webhook)
+    .bind(now)
+    .bind(now)
+    .execute(&pool())
+    .await?
+    .last_insert_rowid();
+
+    select(token_id, id).await
+}
+
+// Replaces the spec and re-arms the alert.
+pub async fn update(token_id: i64, id: i64, spec: &AlertSpec) -> Result<Alert> {
+    let rows = sqlx::query(&format!(
+        "UPDATE {} SET metric=?, symbol=?, op=?, threshold=?, hysteresis=?, cooldown=?, webhook=?,
+         armed=1, updated_at=? WHERE token_id=? AND id=?",
+        ALERTS_TABLE
+    ))
+    .bind(spec.metric)
+    .bind(&spec.symbol)
+    .bind(spec.op)
+    .bind(spec.threshold)
+    .bind(spec.hysteresis)
+    .bind(spec.cooldown)
+    .bind(&spec.webhook)
+    .bind(Utc::now().timestamp())
+    .bind(token_id)
+    .bind(id)
+    .execute(&pool())
+    .await?
+    .rows_affected();
+
+    if rows == 0 {
+        return Err(Error::RowNotFound);
+    }
+    select(token_id, id).await
+}
+
+pub async fn select(token_id: i64, id: i64) -> Result<Alert> {
+    Ok(sqlx::query_as::<_, Alert>(&format!(
+        "SELECT * FROM {} WHERE token_id=? AND id=?",
+        ALERTS_TABLE
+    ))
+    .bind(token_id)
+    .bind(id)
+    .fetch_one(&pool())
+    .await?)
+}
+
+pub async fn select_all(token_id: i64) -> Result<Vec<Alert>> {
+    Ok(sqlx::query_as::<_, Alert>(&format!(
+        "SELECT * FROM {} WHERE token_id=? ORDER BY id",
+        ALERTS_TABLE
+    ))
+    .bind(token_id)
+    .fetch_all(&pool())
+    .await?)
+}
+
+pub async fn select_by_metric(metric: Metric) -> Result<Vec<Alert>> {
+    Ok(sqlx::query_as::<_, Alert>(&format!(
+        "SELECT * FROM {} WHERE metric=? ORDER BY id",
+        ALERTS_TABLE
+    ))
+    .bind(metric)
+    .fetch_all(&pool())
+    .await?)
+}
+
+pub async fn delete(token_id: i64, id: i64) -> Result<()> {
+    let mut tx = pool().begin().await?;
+
+    let rows = sqlx::query(&format!(
+        "DELETE FROM {} WHERE token_id=? AND id=?",
+        ALERTS_TABLE
+    ))
+    .bind(token_id)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if rows == 0 {
+        return Err(Error::RowNotFound);
+    }
+
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE alert_id=?",
+        ALERT_DELIVERIES_TABLE
+    ))
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_state(id: i64, armed: bool, last_triggered_at: Option<i64>) -> Result<()> {
+    sqlx::query(&format!(
+        "UPDATE {} SET armed=?, last_triggered_at=? WHERE id=?",
+        ALERTS_TABLE
+    ))
+    .bind(armed)
+    .bind(last_triggered_at)
+    .bind(id)
+    .execute(&pool())
+    .await?;
+    Ok(())
+}
+
+// Disarms the alert and records a pending delivery in one transaction, so a
+// fired alert always has a delivery. Returns the delivery id.
+pub async fn fire(alert_id: i64, triggered_at: i64, value: f64) -> Result<i64> {
+    let mut tx = pool().begin().await?;
+
+    sqlx::query(&format!(
+        "UPDATE {} SET armed=0, last_triggered_at=? WHERE id=?",
+        ALERTS_TABLE
+    ))
+    .bind(triggered_at)
+    .bind(alert_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let id = sqlx::query(&format!(
+        "INSERT INTO {} (alert_id, triggered_at, value, status, attempts) VALUES (?, ?, ?, ?, 0)",
+        ALERT_DELIVERIES_TABLE
+    ))
+    .bind(alert_id)
+    .bind(triggered_at)
+    .bind(value)
+    .bind(DeliveryStatus::Pending)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub async fn finish_delivery(
+    id: i64,
+    status: DeliveryStatus,
+    attempts: i64,
+    response_code: Option<i64>,
+    error: Option<&str>,
+) -> Result<()> {
+    sqlx::query(&format!(
+        "UPDATE {} SET status=?, attempts=?, response_code=?, error=?, finished_at=? WHERE id=?",
+        ALERT_DELIVERIES_TABLE
+    ))
+    .bind(status)
+    .bind(attempts)
+    .bind(response_code)
+    .bind(error)
+    .bind(Utc::now().timestamp())
+    .bind(id)
+    .execute(&pool())
+    .await?;
+    Ok(())
+}
+
+// A delivery is sent by the process that fired it, so the pending ones a
+// restart left behind will never finish. Returns how many were failed.
+pub async fn fail_pending(error: &str) -> Result<u64> {
+    Ok(sqlx::query(&format!(
+        "UPDATE {} SET status=?, error=?, finished_at=? WHERE status=?",
+        ALERT_DELIVERIES_TABLE
+    ))
+    .bind(DeliveryStatus::Failed)
+    .bind(error)
+    .bind(Utc::now().timestamp())
+    .bind(DeliveryStatus::Pending)
+    .execute(&pool())
+    .await?
+    .rows_affected())
+}
+
+// The most recent deliveries first.
+pub async fn select_deliveries(alert_id: i64, limit: u32) -> Result<Vec<Delivery>> {
+    Ok(sqlx::query_as::<_, Delivery>(&format!(
+        "SELECT * FROM {} WHERE alert_id=? ORDER BY id DESC LIMIT ?",
+        ALERT_DELIVERIES_TABLE
+    ))
+    .bind(alert_id)
+    .bind(limit)
+    .fetch_all(&pool())
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/alert-test.db";
+
+    fn spec() -> AlertSpec {
+        AlertSpec {
+            metric: Metric::Price,
+            symbol: Some("BTC".to_string()),
+            op: Op::Above,
+            threshold: 70000.0,
+            hysteresis: 500.0,
+            cooldown: 3600,
+            webhook: "http://127.0.0.1/hook".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alert_crud() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let _ = std::fs::remove_file(DB_PATH);
+        db::init(DB_PATH).await;
+
+        let alert = create(1, &spec()).await?;
+        assert!(alert.armed);
+        assert_eq!(alert.metric, Metric::Price);
+
+        assert!(matches!(select(2, alert.id).await, Err(Error::RowNotFound)));
+        assert!(select_all(2).await?.is_empty());
+
+        let id = fire(alert.id, 100, 71000.0).await?;
+        let fired = select(1, alert.id).await?;
+        assert!(!fired.armed);
+        assert_eq!(fired.last_triggered_at, Some(100));
+
+        let updated = update(
+            1,
+            alert.id,
+            &AlertSpec {
+                op: Op::Below,
+                ..spec()
+            },
+        )
+        .await?;
+        assert_eq!(updated.op, Op::Below);
+        assert!(updated.armed);
+        assert_eq!(select_by_metric(Metric::Price).await?.len(), 1);
+
+        finish_delivery(id, DeliveryStatus::Failed, 3, Some(500), Some("http 500")).await?;
+        let deliveries = select_deliveries(alert.id, 10).await?;
+        assert_eq!(deliveries[0].status, DeliveryStatus::Failed);
+        assert_eq!(deliveries[0].attempts, 3);
+
+        let id = fire(alert.id, 200, 72000.0).await?;
+        assert_eq!(fail_pending("interrupted").await?, 1);
+        let deliveries = select_deliveries(alert.id, 10).await?;
+        assert_eq!(deliveries[0].id, id);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Failed);
+        assert_eq!(deliveries[0].error.as_deref(), Some("interrupted"));
+        assert_eq!(fail_pending("interrupted").await?, 0);
+
+        assert!(matches!(delete(2, alert.id).await, Err(Error::RowNotFound)));
+        delete(1, alert.id).await?;
+        assert!(select_deliveries(alert.id, 10).await?.is_empty());
+        Ok(())
+    }
+}
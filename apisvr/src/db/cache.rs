@@ -0,0 +1,63 @@
+use super::{pool, Result, CACHE_SNAPSHOTS_TABLE};
+use serde::{Deserialize, Serialize};
+
+// The last good value of a cache entry as JSON, one row per entry.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, sqlx::FromRow)]
+pub struct Snapshot {
+    pub name: String,
+    pub data: String,
+    pub fetched_at: i64,
+    pub source: String,
+}
+
+pub async fn save(name: &str, data: &str, fetched_at: i64, source: &str) -> Result<()> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (name, data, fetched_at, source) VALUES (?, ?, ?, ?)
+         ON CONFLICT(name) DO UPDATE SET
+         data=excluded.data, fetched_at=excluded.fetched_at, source=excluded.source",
+        CACHE_SNAPSHOTS_TABLE
+    ))
+    .bind(name)
+    .bind(data)
+    .bind(fetched_at)
+    .bind(source)
+    .execute(&pool())
+    .await?;
+    Ok(())
+}
+
+pub async fn select(name: &str) -> Result<Snapshot> {
+    Ok(sqlx::query_as::<_, Snapshot>(&format!(
+        "SELECT * FROM {} WHERE name=?",
+        CACHE_SNAPSHOTS_TABLE
+    ))
+    .bind(name)
+    .fetch_one(&pool())
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, Error, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/cache-test.db";
+
+    #[tokio::test]
+    async fn test_save_select() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let _ = std::fs::remove_file(DB_PATH);
+        db::init(DB_PATH).await;
+
+        assert!(matches!(select("stats").await, Err(Error::RowNotFound)));
+
+        save("stats", "{}", 100, "alternative").await?;
+        save("stats", r#"{"a": 1}"#, 200, "alternative").await?;
+
+        let s = select("stats").await?;
+        assert_eq!((s.data.as_str(), s.fetched_at), (r#"{"a": 1}"#, 200));
+        assert_eq!(s.source, "alternative");
+        Ok(())
+    }
+}
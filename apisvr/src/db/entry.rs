@@ -1,12 +1,31 @@
-use super::{pool, ComEntry};
-use anyhow::Result;
+use super::{
+    error::{Error, Result},
+    pool, ComEntry,
+};
+use chrono::Utc;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Page {
+    pub data: Vec<ComEntry>,
+    pub next_cursor: Option<i64>,
+}
 
 async fn _new(table_name: &str, is_unique_data: bool) -> Result<()> {
     let sql = format!(
         "CREATE TABLE IF NOT EXISTS {} (
              id INTEGER PRIMARY KEY,
              uuid TEXT NOT NULL UNIQUE,
-             data TEXT NOT NULL {}
+             data TEXT NOT NULL {},
+             created_at INTEGER NOT NULL DEFAULT 0,
+             updated_at INTEGER NOT NULL DEFAULT 0
              )",
         table_name,
         if is_unique_data { "UNIQUE" } else { "" }
@@ -17,19 +36,27 @@ async fn _new(table_name: &str, is_unique_data: bool) -> Result<()> {
     Ok(())
 }
 
+#[allow(dead_code)]
 pub async fn new(table_name: &str) -> Result<()> {
     _new(table_name, false).await
 }
 
+#[allow(dead_code)]
 pub async fn new_with_unique(table_name: &str) -> Result<()> {
     _new(table_name, true).await
 }
 
+// `Error::RowNotFound` when there is no row with `uuid`.
 pub async fn delete(table_name: &str, uuid: &str) -> Result<()> {
-    sqlx::query(&format!("DELETE FROM {} WHERE uuid=?", table_name))
+    let rows = sqlx::query(&format!("DELETE FROM {} WHERE uuid=?", table_name))
         .bind(uuid)
         .execute(&pool())
-        .await?;
+        .await?
+        .rows_affected();
+
+    if rows == 0 {
+        return Err(Error::RowNotFound);
+    }
     Ok(())
 }
 
@@ -42,50 +69,123 @@ pub async fn delete_all(table_name: &str) -> Result<()> {
 }
 
 pub async fn insert(table_name: &str, uuid: &str, data: &str) -> Result<()> {
+    let now = Utc::now().timestamp();
     sqlx::query(&format!(
-        "INSERT INTO {} (uuid, data) VALUES (?, ?)",
+        "INSERT INTO {} (uuid, data, created_at, updated_at) VALUES (?, ?, ?, ?)",
         table_name
     ))
     .bind(uuid)
     .bind(data)
+    .bind(now)
+    .bind(now)
     .execute(&pool())
     .await?;
     Ok(())
 }
 
-#[allow(dead_code)]
-pub async fn insert_all(table_name: &str, entrys: Vec<ComEntry>) -> (usize, Result<()>) {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InsertMode {
+    // Every row is inserted or none is.
+    #[default]
+    Atomic,
+
+    // Rows with an existing `uuid` are updated; rows that violate another
+    // unique constraint are skipped and reported.
+    Upsert,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Conflict {
+    pub index: usize,
+    pub uuid: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct InsertReport {
+    pub committed: bool,
+    pub inserted: usize,
+    pub updated: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+// The whole batch runs in one transaction on one connection, so every row
+// reuses the same cached prepared statement.
+pub async fn insert_all(
+    table_name: &str,
+    entrys: Vec<ComEntry>,
+    mode: InsertMode,
+) -> Result<InsertReport> {
+    let mut report = InsertReport::default();
     if entrys.is_empty() {
-        return (0, Ok(()));
+        report.committed = true;
+        return Ok(report);
     }
 
-    let entrys_len = entrys.len();
-    let p = pool();
-
-    for (index, ComEntry { uuid, data }) in entrys.into_iter().enumerate() {
-        if let Err(e) = sqlx::query(&format!(
-            "INSERT INTO {} (uuid, data) VALUES (?, ?)",
+    let insert_sql = match mode {
+        InsertMode::Atomic => format!(
+            "INSERT INTO {} (uuid, data, created_at, updated_at) VALUES (?, ?, ?, ?)",
             table_name
-        ))
-        .bind(uuid)
-        .bind(data)
-        .execute(&p)
-        .await
+        ),
+        InsertMode::Upsert => format!(
+            "INSERT INTO {} (uuid, data, created_at, updated_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT(uuid) DO UPDATE SET data=excluded.data, updated_at=excluded.updated_at",
+            table_name
+        ),
+    };
+    let exist_sql = format!("SELECT 1 FROM {} WHERE uuid=?", table_name);
+
+    let now = Utc::now().timestamp();
+    let mut tx = pool().begin().await?;
+
+    for (index, ComEntry { uuid, data, .. }) in entrys.into_iter().enumerate() {
+        let is_exist = mode == InsertMode::Upsert
+            && sqlx::query(&exist_sql)
+                .bind(&uuid)
+                .fetch_optional(&mut *tx)
+                .await?
+                .is_some();
+
+        // A failed statement only rolls back itself, the transaction stays usable.
+        match sqlx::query(&insert_sql)
+            .bind(&uuid)
+            .bind(data)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
         {
-            return (index, Err(anyhow::anyhow!("{e:?}")));
+            Ok(_) if is_exist => report.updated += 1,
+            Ok(_) => report.inserted += 1,
+            Err(e) => match Error::from(e) {
+                Error::UniqueViolation => report.conflicts.push(Conflict { index, uuid }),
+                e => return Err(e),
+            },
         }
     }
 
-    (entrys_len, Ok(()))
+    if mode == InsertMode::Atomic && !report.conflicts.is_empty() {
+        tx.rollback().await?;
+        report.inserted = 0;
+        return Ok(report);
+    }
+
+    tx.commit().await?;
+    report.committed = true;
+
+    Ok(report)
 }
 
 #[allow(dead_code)]
 pub async fn update(table_name: &str, uuid: &str, data: &str) -> Result<()> {
-    sqlx::query(&format!("UPDATE {} SET data=? WHERE uuid=?", table_name))
-        .bind(data)
-        .bind(uuid)
-        .execute(&pool())
-        .await?;
+    sqlx::query(&format!(
+        "UPDATE {} SET data=?, updated_at=? WHERE uuid=?",
+        table_name
+    ))
+    .bind(data)
+    .bind(Utc::now().timestamp())
+    .bind(uuid)
+    .execute(&pool())
+    .await?;
 
     Ok(())
 }
@@ -100,6 +200,7 @@ pub async fn select(table_name: &str, uuid: &str) -> Result<ComEntry> {
     )
 }
 
+#[allow(dead_code)]
 pub async fn select_all(table_name: &str) -> Result<Vec<ComEntry>> {
     Ok(
         sqlx::query_as::<_, ComEntry>(&format!("SELECT * FROM {}", table_name))
@@ -108,6 +209,37 @@ pub async fn select_all(table_name: &str) -> Result<Vec<ComEntry>> {
     )
 }
 
+// Keyset pagination on `id`: `cursor` is the last id of the previous page.
+pub async fn select_page(
+    table_name: &str,
+    cursor: Option<i64>,
+    limit: u32,
+    order: Order,
+) -> Result<Page> {
+    let (cmp, direction, start) = match order {
+        Order::Asc => (">", "ASC", 0),
+        Order::Desc => ("<", "DESC", i64::MAX),
+    };
+
+    let mut data = sqlx::query_as::<_, ComEntry>(&format!(
+        "SELECT * FROM {} WHERE id {} ? ORDER BY id {} LIMIT ?",
+        table_name, cmp, direction
+    ))
+    .bind(cursor.unwrap_or(start))
+    .bind(limit as i64 + 1)
+    .fetch_all(&pool())
+    .await?;
+
+    let next_cursor = if data.len() > limit as usize {
+        data.truncate(limit as usize);
+        data.last().map(|item| item.id)
+    } else {
+        None
+    };
+
+    Ok(Page { data, next_cursor })
+}
+
 #[allow(dead_code)]
 pub async fn is_exist(table_name: &str, uuid: &str) -> bool {
     select(table_name, uuid).await.is_ok()
@@ -115,22 +247,20 @@ pub async fn is_exist(table_name: &str, uuid: &str) -> bool {
 
 #[allow(dead_code)]
 pub async fn drop_table(table_name: &str) -> Result<()> {
-    super::drop_table(&table_name).await
+    super::drop_table(table_name).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db;
+    use crate::db::{self, TEST_MTX};
     use rocket::tokio;
-    use std::sync::Mutex;
 
-    static MTX: Mutex<()> = Mutex::new(());
     const DB_PATH: &str = "/tmp/entry-test.db";
 
     #[tokio::test]
     async fn test_table_new() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         Ok(())
@@ -138,7 +268,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_delete_all() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
@@ -147,19 +277,23 @@ mod tests {
 
     #[tokio::test]
     async fn test_delete_one() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
 
         delete_all("suuid_1").await?;
         insert("suuid_1", "uuid-1", "data-1").await?;
         delete("suuid_1", "uuid-1").await?;
+        assert!(matches!(
+            delete("suuid_1", "uuid-1").await,
+            Err(Error::RowNotFound)
+        ));
         Ok(())
     }
 
     #[tokio::test]
     async fn test_insert() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
@@ -172,28 +306,115 @@ mod tests {
 
     #[tokio::test]
     async fn test_insert_all() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
 
         let entrys = (0..100)
-            .into_iter()
             .map(|index| ComEntry {
                 uuid: format!("uuid-{index}"),
                 data: format!("data-{index}"),
+                ..Default::default()
+            })
+            .collect();
+
+        let report = insert_all("suuid_1", entrys, InsertMode::Atomic).await?;
+        assert!(report.committed);
+        assert_eq!(report.inserted, 100);
+        assert_eq!(select_all("suuid_1").await?.len(), 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_all_atomic_conflict() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        new("suuid_1").await?;
+        delete_all("suuid_1").await?;
+
+        insert("suuid_1", "uuid-1", "data-1").await?;
+
+        let entrys = (0..3)
+            .map(|index| ComEntry {
+                uuid: format!("uuid-{index}"),
+                data: format!("data-{index}-new"),
+                ..Default::default()
             })
             .collect();
 
-        let (counts, _) = insert_all("suuid_1", entrys).await;
-        assert_eq!(counts, 100);
+        let report = insert_all("suuid_1", entrys, InsertMode::Atomic).await?;
+        assert!(!report.committed);
+        assert_eq!(report.inserted, 0);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].index, 1);
+        assert_eq!(report.conflicts[0].uuid, "uuid-1");
+
+        let v = select_all("suuid_1").await?;
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].data, "data-1");
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_insert_all_upsert() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        drop_table("suuid_2").await.ok();
+        new_with_unique("suuid_2").await?;
+
+        insert("suuid_2", "uuid-1", "data-1").await?;
+        insert("suuid_2", "uuid-2", "data-2").await?;
+
+        let entrys = [
+            ("uuid-1", "data-1-new"),
+            ("uuid-3", "data-2"),
+            ("uuid-4", "data-4"),
+        ]
+        .into_iter()
+        .map(|(uuid, data)| ComEntry {
+            uuid: uuid.to_string(),
+            data: data.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+        let report = insert_all("suuid_2", entrys, InsertMode::Upsert).await?;
+        assert!(report.committed);
+        assert_eq!(report.inserted, 1);
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].uuid, "uuid-3");
+
+        assert_eq!(select("suuid_2", "uuid-1").await?.data, "data-1-new");
+        assert_eq!(select("suuid_2", "uuid-4").await?.data, "data-4");
+        assert!(select("suuid_2", "uuid-3").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_unique_violation() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        drop_table("suuid_2").await.ok();
+        new_with_unique("suuid_2").await?;
+
+        insert("suuid_2", "uuid-1", "data-1").await?;
+        assert!(matches!(
+            insert("suuid_2", "uuid-1", "data-2").await,
+            Err(Error::UniqueViolation)
+        ));
+        assert!(matches!(
+            insert("suuid_2", "uuid-2", "data-1").await,
+            Err(Error::UniqueViolation)
+        ));
         Ok(())
     }
 
     #[tokio::test]
     async fn test_update() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
@@ -201,33 +422,37 @@ mod tests {
         insert("suuid_1", "uuid-1", "data-1").await?;
         update("suuid_1", "uuid-1", "data-1-1").await?;
 
-        assert_eq!(
-            select("suuid_1", "uuid-1").await?.data,
-            "data-1-1".to_string()
-        );
+        let item = select("suuid_1", "uuid-1").await?;
+        assert_eq!(item.data, "data-1-1".to_string());
+        assert!(item.updated_at >= item.created_at);
 
         Ok(())
     }
 
     #[tokio::test]
     async fn test_select_one() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
 
-        assert!(select("suuid_1", "uuid-1").await.is_err());
+        assert!(matches!(
+            select("suuid_1", "uuid-1").await,
+            Err(Error::RowNotFound)
+        ));
 
         insert("suuid_1", "uuid-1", "data-1").await?;
         let item = select("suuid_1", "uuid-1").await?;
         assert_eq!(item.uuid, "uuid-1");
         assert_eq!(item.data, "data-1");
+        assert!(item.created_at > 0);
+        assert_eq!(item.created_at, item.updated_at);
         Ok(())
     }
 
     #[tokio::test]
     async fn test_select_all() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
@@ -243,9 +468,50 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_select_page() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        new("suuid_1").await?;
+        delete_all("suuid_1").await?;
+
+        for index in 0..5 {
+            insert(
+                "suuid_1",
+                &format!("uuid-{index}"),
+                &format!("data-{index}"),
+            )
+            .await?;
+        }
+
+        let page = select_page("suuid_1", None, 2, Order::Asc).await?;
+        assert_eq!(page.data.len(), 2);
+        assert_eq!(page.data[0].uuid, "uuid-0");
+        assert_eq!(page.data[1].uuid, "uuid-1");
+
+        let page = select_page("suuid_1", page.next_cursor, 2, Order::Asc).await?;
+        assert_eq!(page.data[0].uuid, "uuid-2");
+        assert_eq!(page.data[1].uuid, "uuid-3");
+
+        let page = select_page("suuid_1", page.next_cursor, 2, Order::Asc).await?;
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].uuid, "uuid-4");
+        assert!(page.next_cursor.is_none());
+
+        let page = select_page("suuid_1", None, 3, Order::Desc).await?;
+        assert_eq!(page.data[0].uuid, "uuid-4");
+        assert_eq!(page.data[2].uuid, "uuid-2");
+
+        let page = select_page("suuid_1", page.next_cursor, 3, Order::Desc).await?;
+        assert_eq!(page.data.len(), 2);
+        assert_eq!(page.data[1].uuid, "uuid-0");
+        assert!(page.next_cursor.is_none());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_drop_table() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         db::init(DB_PATH).await;
         new("suuid_1").await?;
         delete_all("suuid_1").await?;
@@ -0,0 +1,56 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// SQLite primary result codes, see https://www.sqlite.org/rescode.html
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+#[derive(Debug)]
+pub enum Error {
+    RowNotFound,
+    UniqueViolation,
+    Busy,
+    Unavailable,
+    Other(sqlx::Error),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Error::RowNotFound,
+            sqlx::Error::PoolTimedOut => Error::Busy,
+            sqlx::Error::PoolClosed | sqlx::Error::Io(_) => Error::Unavailable,
+            sqlx::Error::Database(ref de) if de.is_unique_violation() => Error::UniqueViolation,
+            sqlx::Error::Database(ref de) => {
+                // Extended result codes keep the primary code in the low byte.
+                match de.code().and_then(|c| c.parse::<i32>().ok()) {
+                    Some(code) if matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED) => Error::Busy,
+                    _ => Error::Other(e),
+                }
+            }
+            e => Error::Other(e),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::RowNotFound => write!(f, "row not found"),
+            Error::UniqueViolation => write!(f, "unique constraint violation"),
+            Error::Busy => write!(f, "database is busy"),
+            Error::Unavailable => write!(f, "database is unavailable"),
+            Error::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Other(e) => Some(e),
+            _ => None,
+        }
+    }
+}
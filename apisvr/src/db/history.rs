@@ -0,0 +1,121 @@
+use super::{pool, Result, PRICE_CANDLES_TABLE};
+use serde::{Deserialize, Serialize};
+
+// Samples are never stored as is. Every sample is folded into the candle of
+// its bucket at each resolution, so downsampling happens on write and a
+// resolution is thinned out by deleting its old buckets.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct Candle {
+    pub time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+pub async fn record(samples: &[(&str, f64)], ts: i64, resolutions: &[i64]) -> Result<()> {
+    let mut tx = pool().begin().await?;
+
+    for resolution in resolutions.iter().filter(|r| **r > 0) {
+        for (symbol, price) in samples {
+            sqlx::query(&format!(
+                "INSERT INTO {} (symbol, resolution, bucket, open, high, low, close, samples)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, 1)
+                 ON CONFLICT(symbol, resolution, bucket) DO UPDATE SET
+                 high=MAX(high, excluded.high), low=MIN(low, excluded.low),
+                 close=excluded.close, samples=samples+1",
+                PRICE_CANDLES_TABLE
+            ))
+            .bind(symbol)
+            .bind(resolution)
+            .bind(ts - ts.rem_euclid(*resolution))
+            .bind(price)
+            .bind(price)
+            .bind(price)
+            .bind(price)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Drops the buckets of `resolution` that start before `before`.
+pub async fn prune(resolution: i64, before: i64) -> Result<u64> {
+    Ok(sqlx::query(&format!(
+        "DELETE FROM {} WHERE resolution=? AND bucket<?",
+        PRICE_CANDLES_TABLE
+    ))
+    .bind(resolution)
+    .bind(before)
+    .execute(&pool())
+    .await?
+    .rows_affected())
+}
+
+// Candles whose bucket starts within [from, to], oldest first.
+pub async fn select(symbol: &str, resolution: i64, from: i64, to: i64) -> Result<Vec<Candle>> {
+    Ok(sqlx::query_as::<_, Candle>(&format!(
+        "SELECT bucket AS time, open, high, low, close FROM {}
+         WHERE symbol=? AND resolution=? AND bucket>=? AND bucket<=? ORDER BY bucket",
+        PRICE_CANDLES_TABLE
+    ))
+    .bind(symbol)
+    .bind(resolution)
+    .bind(from)
+    .bind(to)
+    .fetch_all(&pool())
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/history-test.db";
+
+    #[tokio::test]
+    async fn test_record_select_prune() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let _ = std::fs::remove_file(DB_PATH);
+        db::init(DB_PATH).await;
+
+        for (ts, price) in [(0, 10.0), (30, 12.0), (45, 9.0), (70, 11.0)] {
+            record(&[("BTC", price), ("ETH", 1.0)], ts, &[60, 3600]).await?;
+        }
+
+        let minutes = select("BTC", 60, 0, 3600).await?;
+        assert_eq!(
+            minutes,
+            vec![
+                Candle {
+                    time: 0,
+                    open: 10.0,
+                    high: 12.0,
+                    low: 9.0,
+                    close: 9.0
+                },
+                Candle {
+                    time: 60,
+                    open: 11.0,
+                    high: 11.0,
+                    low: 11.0,
+                    close: 11.0
+                },
+            ]
+        );
+
+        let hours = select("BTC", 3600, 0, 3600).await?;
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].open, hours[0].close), (10.0, 11.0));
+
+        assert_eq!(prune(60, 60).await?, 2);
+        assert_eq!(select("BTC", 60, 0, 3600).await?.len(), 1);
+        assert_eq!(select("BTC", 3600, 0, 3600).await?.len(), 1);
+        Ok(())
+    }
+}
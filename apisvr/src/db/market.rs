@@ -0,0 +1,127 @@
+use super::{history::Candle, pool, Result, MARKET_CLOSES_TABLE, MARKET_SAMPLES_TABLE};
+
+// Intraday samples are stored as they are and pruned after a while, daily
+// closes are folded into one candle per code and trading day and kept.
+pub async fn record_samples(samples: &[(&str, f64)], ts: i64) -> Result<()> {
+    let mut tx = pool().begin().await?;
+
+    for (code, last) in samples {
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (code, time, last) VALUES (?, ?, ?)",
+            MARKET_SAMPLES_TABLE
+        ))
+        .bind(code)
+        .bind(ts)
+        .bind(last)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// `closes` are (code, trading date at 00:00 UTC, last) tuples.
+pub async fn record_closes(closes: &[(&str, i64, f64)]) -> Result<()> {
+    let mut tx = pool().begin().await?;
+
+    for (code, day, last) in closes {
+        sqlx::query(&format!(
+            "INSERT INTO {} (code, day, open, high, low, close) VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(code, day) DO UPDATE SET
+             high=MAX(high, excluded.high), low=MIN(low, excluded.low), close=excluded.close",
+            MARKET_CLOSES_TABLE
+        ))
+        .bind(code)
+        .bind(day)
+        .bind(last)
+        .bind(last)
+        .bind(last)
+        .bind(last)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn prune_samples(before: i64) -> Result<u64> {
+    Ok(sqlx::query(&format!(
+        "DELETE FROM {} WHERE time<?",
+        MARKET_SAMPLES_TABLE
+    ))
+    .bind(before)
+    .execute(&pool())
+    .await?
+    .rows_affected())
+}
+
+// Samples within [from, to] as flat candles, oldest first.
+pub async fn select_samples(code: &str, from: i64, to: i64) -> Result<Vec<Candle>> {
+    Ok(sqlx::query_as::<_, Candle>(&format!(
+        "SELECT time, last AS open, last AS high, last AS low, last AS close FROM {}
+         WHERE code=? AND time>=? AND time<=? ORDER BY time",
+        MARKET_SAMPLES_TABLE
+    ))
+    .bind(code)
+    .bind(from)
+    .bind(to)
+    .fetch_all(&pool())
+    .await?)
+}
+
+pub async fn select_closes(code: &str, from: i64, to: i64) -> Result<Vec<Candle>> {
+    Ok(sqlx::query_as::<_, Candle>(&format!(
+        "SELECT day AS time, open, high, low, close FROM {}
+         WHERE code=? AND day>=? AND day<=? ORDER BY day",
+        MARKET_CLOSES_TABLE
+    ))
+    .bind(code)
+    .bind(from)
+    .bind(to)
+    .fetch_all(&pool())
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/market-history-test.db";
+
+    #[tokio::test]
+    async fn test_record_select_prune() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let _ = std::fs::remove_file(DB_PATH);
+        db::init(DB_PATH).await;
+
+        for (ts, last) in [(0, 10.0), (30, 12.0), (90, 9.0)] {
+            record_samples(&[("000001.SS", last), ("DXY.OTC", 1.0)], ts).await?;
+            record_closes(&[("000001.SS", 0, last)]).await?;
+        }
+
+        let samples = select_samples("000001.SS", 0, 60).await?;
+        assert_eq!(samples.len(), 2);
+        assert_eq!((samples[1].time, samples[1].close), (30, 12.0));
+
+        let closes = select_closes("000001.SS", 0, 86400).await?;
+        assert_eq!(
+            closes,
+            vec![Candle {
+                time: 0,
+                open: 10.0,
+                high: 12.0,
+                low: 9.0,
+                close: 9.0
+            }]
+        );
+
+        assert_eq!(prune_samples(60).await?, 4);
+        assert_eq!(select_samples("000001.SS", 0, 3600).await?.len(), 1);
+        assert_eq!(select_closes("000001.SS", 0, 0).await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,580 @@
+use super::{
+    ALERTS_TABLE, ALERT_DELIVERIES_TABLE, API_TOKENS_TABLE, CACHE_SNAPSHOTS_TABLE,
+    MARKET_CLOSES_TABLE, MARKET_SAMPLES_TABLE, MUSICBOX_ANDROID_FEEDBACK_TABLE,
+    PRICE_CANDLES_TABLE, RSSBOX_ANDROID_BACKUP_TABLE, RSSBOX_ANDROID_FEEDBACK_TABLE,
+    RSSBOX_ANDROID_RSS_CN_TABLE, RSSBOX_ANDROID_RSS_EN_TABLE, VERSIONS_TABLE,
+};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use sqlx::{
+    migrate::MigrateDatabase,
+    sqlite::{Sqlite, SqliteConnection},
+    Connection,
+};
+
+pub const SCHEMA_VERSION_TABLE: &str = "schema_version";
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: fn() -> Vec<String>,
+}
+
+// Append only. A released migration must never be edited or reordered,
+// otherwise databases that already applied it will drift from new ones.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create entry tables",
+        statements: v1_entry_tables,
+    },
+    Migration {
+        version: 2,
+        name: "add entry timestamps",
+        statements: v2_entry_timestamps,
+    },
+    Migration {
+        version: 3,
+        name: "create api tokens table",
+        statements: v3_api_tokens,
+    },
+    Migration {
+        version: 4,
+        name: "create price candles table",
+        statements: v4_price_candles,
+    },
+    Migration {
+        version: 5,
+        name: "create alert tables",
+        statements: v5_alerts,
+    },
+    Migration {
+        version: 6,
+        name: "create cache snapshots table",
+        statements: v6_cache_snapshots,
+    },
+    Migration {
+        version: 7,
+        name: "add cache snapshot source",
+        statements: v7_cache_snapshot_source,
+    },
+    Migration {
+        version: 8,
+        name: "create market history tables",
+        statements: v8_market_history,
+    },
+    Migration {
+        version: 9,
+        name: "key alerts by api token",
+        statements: v9_alert_token_id,
+    },
+];
+
+const ENTRY_TABLES: &[(&str, bool)] = &[
+    (RSSBOX_ANDROID_FEEDBACK_TABLE, false),
+    (RSSBOX_ANDROID_BACKUP_TABLE, false),
+    (VERSIONS_TABLE, false),
+    (RSSBOX_ANDROID_RSS_CN_TABLE, true),
+    (RSSBOX_ANDROID_RSS_EN_TABLE, true),
+    (MUSICBOX_ANDROID_FEEDBACK_TABLE, false),
+];
+
+fn v1_entry_tables() -> Vec<String> {
+    ENTRY_TABLES
+        .iter()
+        .map(|(table_name, is_unique_data)| {
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                 id INTEGER PRIMARY KEY,
+                 uuid TEXT NOT NULL UNIQUE,
+                 data TEXT NOT NULL {}
+                 )",
+                table_name,
+                if *is_unique_data { "UNIQUE" } else { "" }
+            )
+        })
+        .collect()
+}
+
+// Rows written before this migration have no history, so they are stamped
+// with the time of the upgrade.
+fn v2_entry_timestamps() -> Vec<String> {
+    ENTRY_TABLES
+        .iter()
+        .flat_map(|(table_name, _)| {
+            [
+                format!(
+                    "ALTER TABLE {} ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0",
+                    table_name
+                ),
+                format!(
+                    "ALTER TABLE {} ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0",
+                    table_name
+                ),
+                format!(
+                    "UPDATE {} SET created_at = CAST(strftime('%s', 'now') AS INTEGER),
+                     updated_at = CAST(strftime('%s', 'now') AS INTEGER)",
+                    table_name
+                ),
+            ]
+        })
+        .collect()
+}
+
+fn v3_api_tokens() -> Vec<String> {
+    vec![format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+             id INTEGER PRIMARY KEY,
+             name TEXT NOT NULL UNIQUE,
+             secret_hash TEXT NOT NULL UNIQUE,
+             scopes TEXT NOT NULL,
+             created_at INTEGER NOT NULL,
+             expires_at INTEGER,
+             last_used_at INTEGER,
+             revoked_at INTEGER
+             )",
+        API_TOKENS_TABLE
+    )]
+}
+
+// One row per symbol, resolution and bucket start, see `history`.
+fn v4_price_candles() -> Vec<String> {
+    vec![format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+             symbol TEXT NOT NULL,
+             resolution INTEGER NOT NULL,
+             bucket INTEGER NOT NULL,
+             open REAL NOT NULL,
+             high REAL NOT NULL,
+             low REAL NOT NULL,
+             close REAL NOT NULL,
+             samples INTEGER NOT NULL,
+             PRIMARY KEY (symbol, resolution, bucket)
+             ) WITHOUT ROWID",
+        PRICE_CANDLES_TABLE
+    )]
+}
+
+fn v5_alerts() -> Vec<String> {
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+             id INTEGER PRIMARY KEY,
+             owner TEXT NOT NULL,
+             metric TEXT NOT NULL,
+             symbol TEXT,
+             op TEXT NOT NULL,
+             threshold REAL NOT NULL,
+             hysteresis REAL NOT NULL,
+             cooldown INTEGER NOT NULL,
+             webhook TEXT NOT NULL,
+             armed INTEGER NOT NULL,
+             last_triggered_at INTEGER,
+             created_at INTEGER NOT NULL,
+             updated_at INTEGER NOT NULL
+             )",
+            ALERTS_TABLE
+        ),
+        format!(
+            "CREATE INDEX IF NOT EXISTS {0}_owner ON {0} (owner)",
+            ALERTS_TABLE
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+             id INTEGER PRIMARY KEY,
+             alert_id INTEGER NOT NULL,
+             triggered_at INTEGER NOT NULL,
+             value REAL NOT NULL,
+             status TEXT NOT NULL,
+             attempts INTEGER NOT NULL,
+             response_code INTEGER,
+             error TEXT,
+             finished_at INTEGER
+             )",
+            ALERT_DELIVERIES_TABLE
+        ),
+        format!(
+            "CREATE INDEX IF NOT EXISTS {0}_alert_id ON {0} (alert_id)",
+            ALERT_DELIVERIES_TABLE
+        ),
+    ]
+}
+
+fn v6_cache_snapshots() -> Vec<String> {
+    vec![format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+             name TEXT PRIMARY KEY,
+             data TEXT NOT NULL,
+             fetched_at INTEGER NOT NULL
+             ) WITHOUT ROWID",
+        CACHE_SNAPSHOTS_TABLE
+    )]
+}
+
+fn v7_cache_snapshot_source() -> Vec<String> {
+    vec![format!(
+        "ALTER TABLE {} ADD COLUMN source TEXT NOT NULL DEFAULT ''",
+        CACHE_SNAPSHOTS_TABLE
+    )]
+}
+
+// A daily close is keyed by the trading date at 00:00 UTC.
+fn v8_market_history() -> Vec<String> {
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+             code TEXT NOT NULL,
+             time INTEGER NOT NULL,
+             last REAL NOT NULL,
+             PRIMARY KEY (code, time)
+             ) WITHOUT ROWID",
+            MARKET_SAMPLES_TABLE
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+             code TEXT NOT NULL,
+             day INTEGER NOT NULL,
+             open REAL NOT NULL,
+             high REAL NOT NULL,
+             low REAL NOT NULL,
+             close REAL NOT NULL,
+             PRIMARY KEY (code, day)
+             ) WITHOUT ROWID",
+            MARKET_CLOSES_TABLE
+        ),
+    ]
+}
+
+// Alerts were keyed by the name of the caller, which the config tokens share
+// between clients. An alert of a stored token moves to its id, the rest have
+// no single owner and are dropped with their deliveries.
+fn v9_alert_token_id() -> Vec<String> {
+    vec![
+        format!("ALTER TABLE {} ADD COLUMN token_id INTEGER", ALERTS_TABLE),
+        format!(
+            "UPDATE {0} SET token_id = (SELECT id FROM {1} WHERE {1}.name = {0}.owner)",
+            ALERTS_TABLE, API_TOKENS_TABLE
+        ),
+        format!(
+            "DELETE FROM {} WHERE alert_id IN (SELECT id FROM {} WHERE token_id IS NULL)",
+            ALERT_DELIVERIES_TABLE, ALERTS_TABLE
+        ),
+        format!("DELETE FROM {} WHERE token_id IS NULL", ALERTS_TABLE),
+        format!("DROP INDEX IF EXISTS {}_owner", ALERTS_TABLE),
+        format!("ALTER TABLE {} DROP COLUMN owner", ALERTS_TABLE),
+        format!(
+            "CREATE INDEX IF NOT EXISTS {0}_token_id ON {0} (token_id)",
+            ALERTS_TABLE
+        ),
+    ]
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+#[allow(dead_code)]
+pub async fn current_version(conn: &mut SqliteConnection) -> Result<i64> {
+    let is_exist = sqlx::query("SELECT name FROM sqlite_master WHERE type='table' AND name=?")
+        .bind(SCHEMA_VERSION_TABLE)
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some();
+
+    if !is_exist {
+        return Ok(0);
+    }
+
+    Ok(sqlx::query_scalar::<_, i64>(&format!(
+        "SELECT COALESCE(MAX(version), 0) FROM {}",
+        SCHEMA_VERSION_TABLE
+    ))
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+// Migrations run on a dedicated connection before the pool is created, so
+// no pooled connection ever holds a schema from before the upgrade.
+pub async fn migrate(db_path: &str) -> Result<i64> {
+    Sqlite::create_database(db_path).await?;
+
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}", db_path)).await?;
+    let version = run(&mut conn).await;
+    conn.close().await?;
+
+    version
+}
+
+// Apply every pending migration in one transaction and return the resulting
+// schema version. A database written by a newer binary is refused.
+pub async fn run(conn: &mut SqliteConnection) -> Result<i64> {
+    let latest = latest_version();
+    let mut tx = conn.begin().await?;
+
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+             version INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             applied_at INTEGER NOT NULL
+             )",
+        SCHEMA_VERSION_TABLE
+    ))
+    .execute(&mut *tx)
+    .await?;
+
+    let current = sqlx::query_scalar::<_, i64>(&format!(
+        "SELECT COALESCE(MAX(version), 0) FROM {}",
+        SCHEMA_VERSION_TABLE
+    ))
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if current > latest {
+        bail!(
+            "database schema version {} is newer than the supported version {}",
+            current,
+            latest
+        );
+    }
+
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        for sql in (m.statements)() {
+            let rows = sqlx::query(&sql).execute(&mut *tx).await?.rows_affected();
+
+            // Data a migration could not carry over is gone for good, say so.
+            if sql.starts_with("DELETE") && rows > 0 {
+                log::warn!("migration {} deleted {} rows: {}", m.version, rows, sql);
+            }
+        }
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (version, name, applied_at) VALUES (?, ?, ?)",
+            SCHEMA_VERSION_TABLE
+        ))
+        .bind(m.version)
+        .bind(m.name)
+        .bind(Utc::now().timestamp())
+        .execute(&mut *tx)
+        .await?;
+
+        log::info!("applied migration {}: {}", m.version, m.name);
+    }
+
+    tx.commit().await?;
+
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, entry, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/migration-test.db";
+
+    async fn connect() -> Result<SqliteConnection> {
+        Ok(SqliteConnection::connect(&format!("sqlite:{}", DB_PATH)).await?)
+    }
+
+    // The layout `db::init` produced before migrations existed.
+    async fn legacy_init() -> Result<SqliteConnection> {
+        let _ = std::fs::remove_file(DB_PATH);
+        Sqlite::create_database(DB_PATH).await?;
+        let mut conn = connect().await?;
+
+        for (table_name, is_unique_data) in ENTRY_TABLES {
+            sqlx::query(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                     id INTEGER PRIMARY KEY,
+                     uuid TEXT NOT NULL UNIQUE,
+                     data TEXT NOT NULL {}
+                     )",
+                table_name,
+                if *is_unique_data { "UNIQUE" } else { "" }
+            ))
+            .execute(&mut conn)
+            .await?;
+        }
+
+        Ok(conn)
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        assert!(!MIGRATIONS.is_empty());
+        for (index, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, index as i64 + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_fresh_db() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let _ = std::fs::remove_file(DB_PATH);
+
+        assert_eq!(migrate(DB_PATH).await?, latest_version());
+        assert_eq!(
+            current_version(&mut connect().await?).await?,
+            latest_version()
+        );
+
+        db::init(DB_PATH).await;
+        for (table_name, _) in ENTRY_TABLES {
+            assert!(db::is_table_exist(table_name).await.is_ok());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_db() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let mut conn = legacy_init().await?;
+
+        for (table_name, uuid, data) in [
+            (VERSIONS_TABLE, "rssbox", "v1.0.0"),
+            (RSSBOX_ANDROID_RSS_CN_TABLE, "uuid-1", "rss-1"),
+        ] {
+            sqlx::query(&format!(
+                "INSERT INTO {} (uuid, data) VALUES (?, ?)",
+                table_name
+            ))
+            .bind(uuid)
+            .bind(data)
+            .execute(&mut conn)
+            .await?;
+        }
+
+        assert_eq!(current_version(&mut conn).await?, 0);
+        conn.close().await?;
+
+        db::init(DB_PATH).await;
+        assert_eq!(
+            current_version(&mut connect().await?).await?,
+            latest_version()
+        );
+
+        let item = entry::select(VERSIONS_TABLE, "rssbox").await?;
+        assert_eq!(item.data, "v1.0.0");
+        assert!(item.created_at > 0);
+        assert_eq!(item.created_at, item.updated_at);
+
+        assert_eq!(
+            entry::select_all(RSSBOX_ANDROID_RSS_CN_TABLE).await?.len(),
+            1
+        );
+        assert!(
+            entry::insert(RSSBOX_ANDROID_RSS_CN_TABLE, "uuid-2", "rss-1")
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_is_idempotent() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let mut conn = legacy_init().await?;
+
+        run(&mut conn).await?;
+        run(&mut conn).await?;
+
+        let count =
+            sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", SCHEMA_VERSION_TABLE))
+                .fetch_one(&mut conn)
+                .await?;
+        assert_eq!(count, MIGRATIONS.len() as i64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_alert_owners() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let mut conn = legacy_init().await?;
+
+        // The schema before alerts were keyed by token id.
+        sqlx::query(&format!(
+            "CREATE TABLE {} (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_at INTEGER NOT NULL)",
+            SCHEMA_VERSION_TABLE
+        ))
+        .execute(&mut conn)
+        .await?;
+        for m in MIGRATIONS.iter().filter(|m| m.version < 9) {
+            for sql in (m.statements)() {
+                sqlx::query(&sql).execute(&mut conn).await?;
+            }
+            sqlx::query(&format!(
+                "INSERT INTO {} (version, name, applied_at) VALUES (?, ?, 0)",
+                SCHEMA_VERSION_TABLE
+            ))
+            .bind(m.version)
+            .bind(m.name)
+            .execute(&mut conn)
+            .await?;
+        }
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (id, name, secret_hash, scopes, created_at) VALUES (7, 'bot', 'h', 'alerts', 0)",
+            API_TOKENS_TABLE
+        ))
+        .execute(&mut conn)
+        .await?;
+        for (id, owner) in [(1, "bot"), (2, "config:app"), (3, "anonymous")] {
+            sqlx::query(&format!(
+                "INSERT INTO {} (id, owner, metric, op, threshold, hysteresis, cooldown, webhook,
+                 armed, created_at, updated_at) VALUES (?, ?, 'greed_fear', 'below', 20, 0, 0, '', 1, 0, 0)",
+                ALERTS_TABLE
+            ))
+            .bind(id)
+            .bind(owner)
+            .execute(&mut conn)
+            .await?;
+            sqlx::query(&format!(
+                "INSERT INTO {} (alert_id, triggered_at, value, status, attempts) VALUES (?, 0, 0, 'delivered', 1)",
+                ALERT_DELIVERIES_TABLE
+            ))
+            .bind(id)
+            .execute(&mut conn)
+            .await?;
+        }
+
+        run(&mut conn).await?;
+        let alerts =
+            sqlx::query_as::<_, (i64, i64)>(&format!("SELECT id, token_id FROM {}", ALERTS_TABLE))
+                .fetch_all(&mut conn)
+                .await?;
+        assert_eq!(alerts, vec![(1, 7)]);
+
+        let deliveries = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT alert_id FROM {}",
+            ALERT_DELIVERIES_TABLE
+        ))
+        .fetch_all(&mut conn)
+        .await?;
+        assert_eq!(deliveries, vec![1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refuse_newer_db() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        let mut conn = legacy_init().await?;
+        run(&mut conn).await?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (version, name, applied_at) VALUES (?, 'future', 0)",
+            SCHEMA_VERSION_TABLE
+        ))
+        .bind(latest_version() + 1)
+        .execute(&mut conn)
+        .await?;
+        conn.close().await?;
+
+        assert!(migrate(DB_PATH).await.is_err());
+        assert_eq!(
+            current_version(&mut connect().await?).await?,
+            latest_version() + 1
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use sqlx::{
     migrate::MigrateDatabase,
@@ -7,7 +6,16 @@ use sqlx::{
 };
 use std::sync::Mutex;
 
+pub mod alert;
+pub mod cache;
 pub mod entry;
+pub mod error;
+pub mod history;
+pub mod market;
+pub mod migration;
+pub mod token;
+
+pub use error::{Error, Result};
 
 const MAX_CONNECTIONS: u32 = 3;
 
@@ -16,24 +24,43 @@ pub const RSSBOX_ANDROID_RSS_CN_TABLE: &str = "rssbox_android_rss_cn";
 pub const RSSBOX_ANDROID_RSS_EN_TABLE: &str = "rssbox_android_rss_en";
 pub const RSSBOX_ANDROID_BACKUP_TABLE: &str = "rssbox_android_backup";
 pub const VERSIONS_TABLE: &str = "versions";
+pub const API_TOKENS_TABLE: &str = "api_tokens";
+pub const PRICE_CANDLES_TABLE: &str = "price_candles";
+pub const ALERTS_TABLE: &str = "alerts";
+pub const ALERT_DELIVERIES_TABLE: &str = "alert_deliveries";
+pub const CACHE_SNAPSHOTS_TABLE: &str = "cache_snapshots";
+pub const MARKET_SAMPLES_TABLE: &str = "market_samples";
+pub const MARKET_CLOSES_TABLE: &str = "market_closes";
 
 pub const MUSICBOX_ANDROID_FEEDBACK_TABLE: &str = "musicbox_android_feedback";
 
-#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, sqlx::FromRow)]
 pub struct ComEntry {
+    #[serde(default)]
+    pub id: i64,
     pub uuid: String,
     pub data: String,
+
+    // Unix timestamps in seconds, maintained by `entry`.
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
 }
 
 lazy_static! {
     static ref POOL: Mutex<Option<Pool<Sqlite>>> = Mutex::new(None);
 }
 
+// Tests share the global pool, so every db test must hold this lock.
+#[cfg(test)]
+pub static TEST_MTX: rocket::tokio::sync::Mutex<()> = rocket::tokio::sync::Mutex::const_new(());
+
 fn pool() -> Pool<Sqlite> {
     POOL.lock().unwrap().clone().unwrap()
 }
 
-async fn create_db(db_path: &str) -> Result<(), sqlx::Error> {
+async fn create_db(db_path: &str) -> std::result::Result<(), sqlx::Error> {
     Sqlite::create_database(db_path).await?;
 
     let pool = SqlitePoolOptions::new()
@@ -47,31 +74,11 @@ async fn create_db(db_path: &str) -> Result<(), sqlx::Error> {
 }
 
 pub async fn init(db_path: &str) {
-    create_db(db_path).await.expect("create db failed");
-
-    entry::new(RSSBOX_ANDROID_FEEDBACK_TABLE)
-        .await
-        .expect("create rssbox android feedback table failed");
-
-    entry::new(RSSBOX_ANDROID_BACKUP_TABLE)
+    migration::migrate(db_path)
         .await
-        .expect("create rssbox android backup table failed");
+        .expect("apply db migrations failed");
 
-    entry::new(VERSIONS_TABLE)
-        .await
-        .expect("create rssbox versions table failed");
-
-    entry::new_with_unique(RSSBOX_ANDROID_RSS_CN_TABLE)
-        .await
-        .expect("create rssbox android rss cn table failed");
-
-    entry::new_with_unique(RSSBOX_ANDROID_RSS_EN_TABLE)
-        .await
-        .expect("create rssbox android rss en table failed");
-
-    entry::new(MUSICBOX_ANDROID_FEEDBACK_TABLE)
-        .await
-        .expect("create musicbox android feedback table failed");
+    create_db(db_path).await.expect("create db failed");
 }
 
 #[allow(dead_code)]
@@ -97,14 +104,12 @@ pub async fn drop_table(table_name: &str) -> Result<()> {
 mod tests {
     use super::*;
     use rocket::tokio;
-    use std::sync::Mutex;
 
-    static MTX: Mutex<()> = Mutex::new(());
     const DB_PATH: &str = "/tmp/db-test.db";
 
     #[tokio::test]
     async fn test_db_is_table_exist() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         init(DB_PATH).await;
         entry::new("trash").await?;
 
@@ -115,7 +120,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_db_drop_table() -> Result<()> {
-        let _mtx = MTX.lock().unwrap();
+        let _mtx = TEST_MTX.lock().await;
         init(DB_PATH).await;
         entry::new("trash").await?;
 
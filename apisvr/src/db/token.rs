@@ -0,0 +1,249 @@
+use super::{pool, Error, Result, API_TOKENS_TABLE};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub const SECRET_PREFIX: &str = "apisvr_";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+
+    // Space separated, see `middleware::auth::scope`.
+    pub scopes: String,
+
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiToken {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scopes.split_whitespace()
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|t| t > now)
+    }
+}
+
+// Only the hash is stored, a lost secret can not be recovered, only replaced.
+pub fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn new_secret() -> String {
+    format!(
+        "{}{}{}",
+        SECRET_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+// Returns the stored token and its secret, which is never available again.
+pub async fn create(
+    name: &str,
+    scopes: &[&str],
+    expires_at: Option<i64>,
+) -> Result<(ApiToken, String)> {
+    let secret = new_secret();
+
+    let id = sqlx::query(&format!(
+        "INSERT INTO {} (name, secret_hash, scopes, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+        API_TOKENS_TABLE
+    ))
+    .bind(name)
+    .bind(hash(&secret))
+    .bind(scopes.join(" "))
+    .bind(Utc::now().timestamp())
+    .bind(expires_at)
+    .execute(&pool())
+    .await?
+    .last_insert_rowid();
+
+    Ok((select(id).await?, secret))
+}
+
+pub async fn select(id: i64) -> Result<ApiToken> {
+    Ok(
+        sqlx::query_as::<_, ApiToken>(&format!("SELECT * FROM {} WHERE id=?", API_TOKENS_TABLE))
+            .bind(id)
+            .fetch_one(&pool())
+            .await?,
+    )
+}
+
+pub async fn select_all() -> Result<Vec<ApiToken>> {
+    Ok(
+        sqlx::query_as::<_, ApiToken>(&format!("SELECT * FROM {} ORDER BY id", API_TOKENS_TABLE))
+            .fetch_all(&pool())
+            .await?,
+    )
+}
+
+// Whether an active token carries any of `scopes`.
+pub async fn has_active(scopes: &[&str]) -> Result<bool> {
+    let rows: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT scopes FROM {} WHERE revoked_at IS NULL AND (expires_at IS NULL OR expires_at > ?)",
+        API_TOKENS_TABLE
+    ))
+    .bind(Utc::now().timestamp())
+    .fetch_all(&pool())
+    .await?;
+    Ok(rows
+        .iter()
+        .any(|(v,)| v.split_whitespace().any(|s| scopes.contains(&s))))
+}
+
+// Looks up an active token by its secret and records the use.
+pub async fn verify(secret: &str) -> Result<ApiToken> {
+    let mut token = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT * FROM {} WHERE secret_hash=?",
+        API_TOKENS_TABLE
+    ))
+    .bind(hash(secret))
+    .fetch_one(&pool())
+    .await?;
+
+    let now = Utc::now().timestamp();
+    if !token.is_active(now) {
+        return Err(Error::RowNotFound);
+    }
+
+    sqlx::query(&format!(
+        "UPDATE {} SET last_used_at=? WHERE id=?",
+        API_TOKENS_TABLE
+    ))
+    .bind(now)
+    .bind(token.id)
+    .execute(&pool())
+    .await?;
+    token.last_used_at = Some(now);
+
+    Ok(token)
+}
+
+pub async fn revoke(id: i64) -> Result<ApiToken> {
+    let rows = sqlx::query(&format!(
+        "UPDATE {} SET revoked_at=? WHERE id=? AND revoked_at IS NULL",
+        API_TOKENS_TABLE
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(id)
+    .execute(&pool())
+    .await?
+    .rows_affected();
+
+    if rows == 0 {
+        return Err(Error::RowNotFound);
+    }
+
+    select(id).await
+}
+
+// Swaps the secret of an active token in one statement, the old secret stops
+// working as the new one starts. The id stays, and so do the alerts it owns.
+pub async fn rotate(id: i64) -> Result<(ApiToken, String)> {
+    let secret = new_secret();
+
+    let rows = sqlx::query(&format!(
+        "UPDATE {} SET secret_hash=?, last_used_at=NULL WHERE id=? AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > ?)",
+        API_TOKENS_TABLE
+    ))
+    .bind(hash(&secret))
+    .bind(id)
+    .bind(Utc::now().timestamp())
+    .execute(&pool())
+    .await?
+    .rows_affected();
+
+    if rows == 0 {
+        return Err(Error::RowNotFound);
+    }
+
+    Ok((select(id).await?, secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, TEST_MTX};
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/token-test.db";
+
+    async fn init() -> Result<()> {
+        db::init(DB_PATH).await;
+        sqlx::query(&format!("DELETE FROM {}", API_TOKENS_TABLE))
+            .execute(&pool())
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_verify() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        init().await?;
+
+        let (token, secret) = create("ci", &["feedback:read", "backup:rssbox"], None).await?;
+        assert!(secret.starts_with(SECRET_PREFIX));
+        assert_eq!(
+            token.scopes().collect::<Vec<_>>(),
+            vec!["feedback:read", "backup:rssbox"]
+        );
+        assert!(token.last_used_at.is_none());
+
+        let verified = verify(&secret).await?;
+        assert_eq!(verified.id, token.id);
+        assert!(select(token.id).await?.last_used_at.is_some());
+
+        assert!(matches!(
+            verify("apisvr_wrong").await,
+            Err(Error::RowNotFound)
+        ));
+        assert!(matches!(
+            create("ci", &[], None).await,
+            Err(Error::UniqueViolation)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_expire() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        init().await?;
+
+        let (token, secret) = create("revoked", &["admin"], None).await?;
+        assert!(revoke(token.id).await?.revoked_at.is_some());
+        assert!(verify(&secret).await.is_err());
+        assert!(matches!(revoke(token.id).await, Err(Error::RowNotFound)));
+
+        let (_, secret) = create("expired", &["admin"], Some(Utc::now().timestamp() - 1)).await?;
+        assert!(verify(&secret).await.is_err());
+
+        assert_eq!(select_all().await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rotate() -> Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        init().await?;
+
+        let (token, old) = create("rotated", &["alerts"], None).await?;
+        verify(&old).await?;
+        let (rotated, new) = rotate(token.id).await?;
+        assert_eq!((rotated.id, rotated.scopes), (token.id, token.scopes));
+        assert!(rotated.last_used_at.is_none());
+        assert!(verify(&old).await.is_err());
+        assert_eq!(verify(&new).await?.id, token.id);
+
+        revoke(token.id).await?;
+        assert!(matches!(rotate(token.id).await, Err(Error::RowNotFound)));
+        Ok(())
+    }
+}
@@ -13,14 +13,19 @@ use std::io::Write;
 use std::net::IpAddr;
 use std::str::FromStr;
 
+mod cli;
 mod config;
 mod controller;
 mod db;
 mod middleware;
 mod response;
+mod scheduler;
 
 use config::conf;
-use middleware::{auth, cors};
+use middleware::{
+    auth::{self, scope, MountGuarded},
+    cors, request_id,
+};
 
 #[launch]
 async fn rocket() -> _ {
@@ -29,9 +34,14 @@ async fn rocket() -> _ {
     debug!("start...");
 
     conf::init();
-    response::init();
     db::init(config::db_path().to_str().expect("db_path is invalid")).await;
 
+    if cli::run(&std::env::args().skip(1).collect::<Vec<_>>()).await {
+        std::process::exit(0);
+    }
+
+    response::init().await;
+
     server_start()
 }
 
@@ -41,44 +51,95 @@ fn server_start() -> Rocket<rocket::Build> {
     config.address = IpAddr::from_str(conf::server().listen_address.as_str()).unwrap();
 
     rocket::custom(config)
+        .attach(request_id::RequestId)
         .attach(cors::Cors)
-        .attach(auth::Auth)
-        .mount(
+        .attach(auth::SelfCheck)
+        .register(
+            "/",
+            catchers![
+                controller::catcher::bad_request,
+                controller::catcher::unauthorized,
+                controller::catcher::forbidden,
+                controller::catcher::not_found,
+                controller::catcher::payload_too_large,
+                controller::catcher::unprocessable_entity,
+                controller::catcher::default,
+            ],
+        )
+        .mount_guarded(
             "/",
-            routes![
-                controller::ping::ping,
-                controller::cryptocurrency::latest,
-                controller::cryptocurrency::greed_fear,
-                controller::market::latest,
-                auth::unauthorized,
-                controller::versions::update,
-                controller::versions::get,
+            guarded![
+                public => [
+                    controller::ping::ping,
+                    controller::health::health,
+                    cors::preflight,
+                    controller::cryptocurrency::latest,
+                    controller::cryptocurrency::latest_raw,
+                    controller::cryptocurrency::history,
+                    controller::cryptocurrency::stats,
+                    controller::cryptocurrency::stats_raw,
+                    controller::cryptocurrency::portfolio,
+                    controller::market::latest,
+                    controller::market::latest_raw,
+                    controller::market::quote,
+                    controller::market::history,
+                    controller::stream::stream,
+                    controller::versions::get,
+                ],
+                scope::VersionsWrite => [controller::versions::update],
             ],
         )
-        .mount(
+        .mount_guarded(
             "/rssbox/android",
-            routes![
-                controller::feedback::rssbox_android::all,
-                controller::feedback::rssbox_android::insert,
-                controller::feedback::rssbox_android::delete,
-                controller::backup_recover::rssbox_android::backup,
-                controller::backup_recover::rssbox_android::recover,
+            guarded![
+                scope::FeedbackRead => [controller::feedback::rssbox_android::all],
+                scope::FeedbackRssbox => [controller::feedback::rssbox_android::insert],
+                scope::FeedbackDelete => [controller::feedback::rssbox_android::delete],
+                scope::BackupRssbox => [
+                    controller::backup_recover::rssbox_android::backup,
+                    controller::backup_recover::rssbox_android::recover,
+                ],
             ],
         )
-        .mount(
+        .mount_guarded(
             "/rssbox/rss/list",
-            routes![
-                controller::rss::all,
-                controller::rss::insert,
-                controller::rss::delete,
+            guarded![
+                public => [controller::rss::all],
+                scope::RssWrite => [controller::rss::insert],
+                scope::RssAdmin => [controller::rss::delete, controller::rss::bulk_insert],
             ],
         )
-        .mount(
+        .mount_guarded(
             "/musicbox",
-            routes![
-                controller::feedback::musicbox_android::all,
-                controller::feedback::musicbox_android::insert,
-                controller::feedback::musicbox_android::delete,
+            guarded![
+                scope::FeedbackRead => [controller::feedback::musicbox_android::all],
+                scope::FeedbackMusicbox => [controller::feedback::musicbox_android::insert],
+                scope::FeedbackDelete => [controller::feedback::musicbox_android::delete],
+            ],
+        )
+        .mount_guarded(
+            "/alerts",
+            guarded![
+                scope::Alerts => [
+                    controller::alert::all,
+                    controller::alert::create,
+                    controller::alert::get,
+                    controller::alert::update,
+                    controller::alert::delete,
+                    controller::alert::deliveries,
+                ],
+            ],
+        )
+        .mount_guarded(
+            "/admin",
+            guarded![
+                scope::TokensAdmin => [
+                    controller::token::create,
+                    controller::token::all,
+                    controller::token::revoke,
+                    controller::token::rotate,
+                ],
+                scope::Admin => [controller::health::errors, controller::job::all],
             ],
         )
 }
@@ -104,7 +165,7 @@ fn init_logger() {
                     .file()
                     .unwrap_or("None")
                     .split('/')
-                    .last()
+                    .next_back()
                     .unwrap_or("None"),
                 record.line().unwrap_or(0),
                 record.args()
@@ -1,108 +1,600 @@
-use crate::config;
-use rocket::fairing::{Fairing, Info, Kind};
+use crate::{
+    config,
+    db::{self, token},
+    response::data,
+};
 use rocket::{
-    http::{hyper::header, uri::Origin, Method, Status},
-    Data, Request,
+    fairing::{self, Fairing, Info, Kind},
+    http::{hyper::header, Method, Status},
+    outcome::Outcome,
+    request::{self, FromRequest},
+    response::Responder,
+    Build, Request, Response, Rocket, Route,
 };
+use std::{fmt, marker::PhantomData, sync::Mutex};
+
+pub trait Scope: Send + Sync + 'static {
+    const NAME: &'static str;
+
+    // The config token of the app that owns this scope. Scopes without one
+    // are bootstrapped by the admin token only.
+    fn app_token() -> Option<String> {
+        None
+    }
+}
+
+macro_rules! scope {
+    ($ty: ident, $name: expr) => {
+        pub struct $ty;
+
+        impl Scope for $ty {
+            const NAME: &'static str = $name;
+        }
+    };
+    ($ty: ident, $name: expr, $app: ident) => {
+        pub struct $ty;
 
-pub struct Auth;
+        impl Scope for $ty {
+            const NAME: &'static str = $name;
 
-#[get("/unauthorized")]
-pub fn unauthorized() -> Status {
-    Status::Unauthorized
+            fn app_token() -> Option<String> {
+                Some(config::auth_token().$app)
+            }
+        }
+    };
+}
+
+pub mod scope {
+    use super::*;
+
+    scope!(Admin, "admin");
+    scope!(FeedbackRead, "feedback:read");
+    scope!(FeedbackDelete, "feedback:delete");
+    scope!(FeedbackRssbox, "feedback:rssbox", rssbox_android);
+    scope!(FeedbackMusicbox, "feedback:musicbox", musicbox_android);
+    scope!(BackupRssbox, "backup:rssbox", rssbox_android);
+    scope!(RssWrite, "rss:write", rssbox_android);
+    scope!(RssAdmin, "rss:admin");
+    scope!(VersionsWrite, "versions:write");
+    scope!(TokensAdmin, "tokens:admin");
+    scope!(Alerts, "alerts");
+
+    pub const ALL: &[&str] = &[
+        Admin::NAME,
+        FeedbackRead::NAME,
+        FeedbackDelete::NAME,
+        FeedbackRssbox::NAME,
+        FeedbackMusicbox::NAME,
+        BackupRssbox::NAME,
+        RssWrite::NAME,
+        RssAdmin::NAME,
+        VersionsWrite::NAME,
+        TokensAdmin::NAME,
+        Alerts::NAME,
+    ];
+}
+
+// Request guard for routes that require the scope `S`. It accepts the config
+// tokens as bootstrap credentials and tokens from the database store.
+pub struct Scoped<S: Scope> {
+    pub owner: String,
+
+    // Only set for a token from the database store, the config tokens and
+    // open routes are shared by every client.
+    pub token_id: Option<i64>,
+    _scope: PhantomData<S>,
+}
+
+impl<S: Scope> Scoped<S> {
+    fn new(owner: String, token_id: Option<i64>) -> Self {
+        Self {
+            owner,
+            token_id,
+            _scope: PhantomData,
+        }
+    }
+}
+
+fn bearer<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    let value = request.headers().get_one(header::AUTHORIZATION.as_str())?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("Bearer")
+        .then_some(token.trim())
+}
+
+fn is_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0_u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+async fn authenticate<S: Scope>(secret: &str) -> Result<Scoped<S>, Status> {
+    let config_tokens = config::auth_token();
+    if !config_tokens.admin.is_empty() && is_equal(secret, &config_tokens.admin) {
+        return Ok(Scoped::new("config:admin".to_string(), None));
+    }
+
+    if let Some(token) = S::app_token().filter(|t| !t.is_empty()) {
+        if is_equal(secret, &token) {
+            return Ok(Scoped::new("config:app".to_string(), None));
+        }
+    }
+
+    if !secret.starts_with(token::SECRET_PREFIX) {
+        return Err(Status::Unauthorized);
+    }
+
+    match token::verify(secret).await {
+        Ok(t) if t.scopes().any(|s| s == S::NAME || s == scope::Admin::NAME) => {
+            Ok(Scoped::new(t.name, Some(t.id)))
+        }
+        Ok(_) => Err(Status::Forbidden),
+        Err(db::Error::RowNotFound) => Err(Status::Unauthorized),
+        Err(e) => {
+            log::warn!("verify api token error: {e:?}");
+            Err(Status::ServiceUnavailable)
+        }
+    }
+}
+
+// An empty config token leaves the routes it protects open until a token
+// that grants their scope is issued. Token management is never open, the CLI
+// bootstraps it.
+async fn is_open<S: Scope>() -> Result<bool, Status> {
+    let config_token = S::app_token().unwrap_or_else(|| config::auth_token().admin);
+    if S::NAME == scope::TokensAdmin::NAME || !config_token.is_empty() {
+        return Ok(false);
+    }
+
+    match token::has_active(&[S::NAME, scope::Admin::NAME]).await {
+        Ok(v) => Ok(!v),
+        Err(e) => {
+            log::warn!("count api tokens error: {e:?}");
+            Err(Status::ServiceUnavailable)
+        }
+    }
 }
 
 #[rocket::async_trait]
-impl Fairing for Auth {
+impl<'r, S: Scope> FromRequest<'r> for Scoped<S> {
+    type Error = ();
+
+    // A rejected token is never let in, not even on an open route.
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let result = match bearer(request) {
+            Some(secret) => authenticate::<S>(secret).await,
+            None => match is_open::<S>().await {
+                Ok(true) => Ok(Scoped::new("anonymous".to_string(), None)),
+                Ok(false) => Err(Status::Unauthorized),
+                Err(status) => Err(status),
+            },
+        };
+
+        match result {
+            Ok(auth) => {
+                request
+                    .local_cache(Passed::default)
+                    .0
+                    .lock()
+                    .unwrap()
+                    .push(S::NAME);
+                Outcome::Success(auth)
+            }
+            Err(status) => Outcome::Error((status, ())),
+        }
+    }
+}
+
+// The scopes whose guard let the request in, checked against the scope its
+// route is mounted with.
+#[derive(Default)]
+struct Passed(Mutex<Vec<&'static str>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Public,
+    Scope(&'static str),
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Public => write!(f, "public"),
+            Access::Scope(name) => write!(f, "scope:{name}"),
+        }
+    }
+}
+
+// `routes!` grouped by the auth requirement of their handlers, for
+// `mount_guarded`. A `public` handler lets every request in, the others must
+// take the `Scoped` guard of the scope they are listed under:
+//
+//     guarded![public => [ping], scope::Admin => [jobs]]
+#[macro_export]
+macro_rules! guarded {
+    (@access public) => {
+        $crate::middleware::auth::Access::Public
+    };
+    (@access $($scope: ident)::+) => {
+        $crate::middleware::auth::Access::Scope(
+            <$($scope)::+ as $crate::middleware::auth::Scope>::NAME,
+        )
+    };
+    ($($($access: ident)::+ => [$($($handler: ident)::+),* $(,)?]),* $(,)?) => {{
+        let mut routes = ::std::vec::Vec::new();
+        $(
+            let access = $crate::guarded!(@access $($access)::+);
+            routes.extend(routes![$($($handler)::+),*].into_iter().map(|r| (r, access)));
+        )*
+        routes
+    }};
+}
+
+// The requirement of every route mounted with `mount_guarded`, by method and
+// mounted uri.
+#[derive(Default)]
+struct Declared(Mutex<Vec<(Method, String, Access)>>);
+
+impl Declared {
+    fn access(&self, method: Method, uri: &str) -> Option<Access> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(m, u, _)| *m == method && u == uri)
+            .map(|(_, _, access)| *access)
+    }
+}
+
+pub trait MountGuarded {
+    fn mount_guarded(self, base: &str, routes: Vec<(Route, Access)>) -> Self;
+}
+
+impl MountGuarded for Rocket<Build> {
+    fn mount_guarded(self, base: &str, routes: Vec<(Route, Access)>) -> Self {
+        let rocket = if self.state::<Declared>().is_some() {
+            self
+        } else {
+            self.manage(Declared::default())
+        };
+
+        if let Some(declared) = rocket.state::<Declared>() {
+            let mut declared = declared.0.lock().unwrap();
+            for (route, access) in &routes {
+                // Rebased the way `mount` does it.
+                if let Ok(r) = route.clone().map_base(|old| format!("{base}{old}")) {
+                    declared.push((r.method, r.uri.as_str().to_string(), *access));
+                }
+            }
+        }
+
+        let routes = routes.into_iter().map(|(r, _)| r).collect::<Vec<_>>();
+        rocket.mount(base, routes)
+    }
+}
+
+// Public although their method changes data. Each one only reads, it takes a
+// POST for a request body too large for a query string.
+const READ_ONLY: &[(Method, &str)] = &[(Method::Post, "/cryptocurrency/portfolio")];
+
+fn is_mutating(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Post | Method::Put | Method::Patch | Method::Delete
+    )
+}
+
+// Refuses to start when a route is not declared or a public one changes data,
+// and turns the success of a scoped route whose handler did not check its
+// scope into a 500.
+pub struct SelfCheck;
+
+#[rocket::async_trait]
+impl Fairing for SelfCheck {
     fn info(&self) -> Info {
         Info {
-            name: "Check auth headers from request",
-            kind: Kind::Request,
+            name: "Check every route declares and checks its auth requirement",
+            kind: Kind::Ignite | Kind::Response,
         }
     }
 
-    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
-        match request.method() {
-            Method::Delete => {
-                let prefix_paths = vec![
-                    "/rssbox/android/feedback",
-                    "/rssbox/rss/list/cn",
-                    "/rssbox/rss/list/en",
-                ];
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let mut is_ok = true;
 
-                let token = config::auth_token().admin;
-                if !handle_unauthorized(request, prefix_paths, &token) {
-                    return;
+        for route in rocket.routes() {
+            let path = route.uri.path();
+            let access = rocket
+                .state::<Declared>()
+                .and_then(|d| d.access(route.method, route.uri.as_str()));
+
+            match access {
+                None => {
+                    log::error!(
+                        "{} {} is not mounted with `mount_guarded`",
+                        route.method,
+                        path
+                    );
+                    is_ok = false;
                 }
-            }
-            Method::Get => {
-                let prefix_paths = vec!["/rssbox/android/recover"];
-                let token = config::auth_token().rssbox_android;
-                if !handle_unauthorized(request, prefix_paths, &token) {
-                    return;
+                Some(Access::Public)
+                    if is_mutating(route.method) && !READ_ONLY.contains(&(route.method, path)) =>
+                {
+                    log::error!("{} {} changes data without a guard", route.method, path);
+                    is_ok = false;
                 }
+                Some(access) => log::info!("{} {} => {}", route.method, path, access),
             }
-            Method::Post => {
-                let prefix_paths = vec!["/latest/version"];
-                let token = config::auth_token().admin;
-                if !handle_unauthorized(request, prefix_paths, &token) {
-                    return;
-                }
+        }
 
-                let prefix_paths = vec!["/rssbox/android/backup"];
-                let token = config::auth_token().rssbox_android;
-                if !handle_unauthorized(request, prefix_paths, &token) {
-                    return;
-                }
+        if is_ok {
+            Ok(rocket)
+        } else {
+            Err(rocket)
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let (Some(route), Some(declared)) = (request.route(), request.rocket().state::<Declared>())
+        else {
+            return;
+        };
+        let Some(Access::Scope(name)) = declared.access(route.method, route.uri.as_str()) else {
+            return;
+        };
+
+        let is_passed = request
+            .local_cache(Passed::default)
+            .0
+            .lock()
+            .unwrap()
+            .contains(&name);
+        if response.status().code < 400 && !is_passed {
+            log::error!(
+                "{} {} is mounted with scope {name} but its handler does not check it",
+                route.method,
+                route.uri.path()
+            );
+            let error = data::Data::error(Status::InternalServerError, "internal server error");
+            if let Ok(r) = error.respond_to(request) {
+                response.merge(r);
             }
-            _ => (),
         }
     }
 }
 
-fn handle_unauthorized(request: &mut Request, prefix_paths: Vec<&str>, token: &str) -> bool {
-    let mut is_continue = true;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TEST_MTX;
+    use rocket::error::ErrorKind;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio;
+
+    const DB_PATH: &str = "/tmp/auth-test.db";
 
-    let (is_prefix, is_auth) = rssbox_android(request, prefix_paths, &token);
-    if is_prefix && !is_auth {
-        navigate_unauthorized(request);
-        is_continue = false;
+    #[get("/feedbacks")]
+    fn feedbacks(_auth: Scoped<scope::FeedbackRead>) -> &'static str {
+        "feedbacks"
     }
 
-    is_continue
-}
+    #[post("/feedback")]
+    fn feedback(auth: Scoped<scope::FeedbackRssbox>) -> String {
+        auth.owner
+    }
 
-fn navigate_unauthorized(request: &mut Request) {
-    request.set_method(Method::Get);
-    request.set_uri(Origin::parse("/unauthorized").unwrap());
-}
+    #[get("/tokens")]
+    fn tokens(_auth: Scoped<scope::TokensAdmin>) -> &'static str {
+        "tokens"
+    }
+
+    #[get("/feedbacks/count")]
+    fn count() -> &'static str {
+        "0"
+    }
+
+    // The path and method of a guarded route, the guard is what counts.
+    #[delete("/feedback/<uuid>")]
+    fn unguarded(uuid: &str) -> String {
+        uuid.to_string()
+    }
 
-fn rssbox_android(request: &Request, prefix_paths: Vec<&str>, token: &str) -> (bool, bool) {
-    let path = request.uri().path().as_str();
+    #[post("/feedback/draft")]
+    fn public_post() -> &'static str {
+        "draft"
+    }
 
-    if prefix_paths
-        .into_iter()
-        .filter(|&item| path.starts_with(item))
-        .collect::<Vec<_>>()
-        .is_empty()
-    {
-        return (false, false);
+    async fn self_check(rocket: Rocket<Build>) -> bool {
+        match Client::tracked(rocket.attach(SelfCheck)).await {
+            Ok(_) => true,
+            Err(e) => {
+                assert!(matches!(e.kind(), ErrorKind::FailedFairings(_)));
+                false
+            }
+        }
     }
 
-    if token.is_empty() {
-        return (true, true);
+    fn set_tokens(admin: &str, rssbox_android: &str) {
+        let mut config = config::conf::CONFIG.lock().unwrap();
+        config.auth_token.admin = admin.to_string();
+        config.auth_token.rssbox_android = rssbox_android.to_string();
+    }
+
+    async fn status(client: &Client, method: Method, uri: &str, token: Option<&str>) -> Status {
+        let mut req = client.req(method, uri.to_string());
+        if let Some(token) = token {
+            req.add_header(Header::new("Authorization", format!("Bearer {token}")));
+        }
+        req.dispatch().await.status()
+    }
+
+    #[tokio::test]
+    async fn test_guards() -> anyhow::Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        set_tokens("admin-token", "app-token");
+
+        let (_, reader) = token::create(
+            &format!("reader-{}", uuid::Uuid::new_v4()),
+            &[scope::FeedbackRead::NAME],
+            None,
+        )
+        .await?;
+
+        let client =
+            Client::tracked(rocket::build().mount("/rssbox/android", routes![feedbacks, feedback]))
+                .await?;
+
+        let (get, post) = (Method::Get, Method::Post);
+        let (list, submit) = ("/rssbox/android/feedbacks", "/rssbox/android/feedback");
+
+        assert_eq!(status(&client, get, list, None).await, Status::Unauthorized);
+        assert_eq!(
+            status(&client, get, list, Some("app-token")).await,
+            Status::Unauthorized
+        );
+        assert_eq!(
+            status(&client, get, list, Some("admin-token")).await,
+            Status::Ok
+        );
+        assert_eq!(status(&client, get, list, Some(&reader)).await, Status::Ok);
+        assert_eq!(
+            status(&client, get, list, Some("apisvr_unknown")).await,
+            Status::Unauthorized
+        );
+
+        assert_eq!(
+            status(&client, post, submit, Some("app-token")).await,
+            Status::Ok
+        );
+        assert_eq!(
+            status(&client, post, submit, Some("admin-token")).await,
+            Status::Ok
+        );
+        assert_eq!(
+            status(&client, post, submit, Some(&reader)).await,
+            Status::Forbidden
+        );
+
+        // Without config tokens a rejected token is still rejected, and a
+        // route stays closed while an active token grants its scope.
+        set_tokens("", "");
+        assert_eq!(status(&client, get, list, None).await, Status::Unauthorized);
+        assert_eq!(status(&client, post, submit, None).await, Status::Ok);
+        assert_eq!(
+            status(&client, get, list, Some("apisvr_unknown")).await,
+            Status::Unauthorized
+        );
+        assert_eq!(
+            status(&client, post, submit, Some(&reader)).await,
+            Status::Forbidden
+        );
+
+        for t in token::select_all().await? {
+            token::revoke(t.id).await.ok();
+        }
+        assert!(!token::has_active(&[scope::FeedbackRead::NAME]).await?);
+        assert_eq!(status(&client, get, list, None).await, Status::Ok);
+
+        // An admin token grants every scope.
+        token::create(
+            &format!("admin-{}", uuid::Uuid::new_v4()),
+            &[scope::Admin::NAME],
+            None,
+        )
+        .await?;
+        assert_eq!(status(&client, get, list, None).await, Status::Unauthorized);
+        assert_eq!(
+            status(&client, post, submit, None).await,
+            Status::Unauthorized
+        );
+        assert_eq!(
+            status(&client, get, list, Some("apisvr_unknown")).await,
+            Status::Unauthorized
+        );
+        Ok(())
     }
 
-    if let Some(http_token) = request.headers().get(header::AUTHORIZATION.as_str()).next() {
-        let http_token = http_token
-            .split_whitespace()
-            .into_iter()
-            .collect::<Vec<_>>();
-        if http_token.len() != 2 && http_token[0] != "Bearer" {
-            return (true, false);
+    #[tokio::test]
+    async fn test_tokens_admin_never_open() -> anyhow::Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        db::init(DB_PATH).await;
+        for t in token::select_all().await? {
+            token::revoke(t.id).await.ok();
         }
-        return (true, token == http_token[1]);
+        set_tokens("", "");
+
+        let client = Client::tracked(rocket::build().mount("/admin", routes![tokens])).await?;
+        assert_eq!(
+            status(&client, Method::Get, "/admin/tokens", None).await,
+            Status::Unauthorized
+        );
+        Ok(())
     }
 
-    return (true, false);
+    #[tokio::test]
+    async fn test_self_check() {
+        let guarded = || {
+            rocket::build().mount_guarded(
+                "/rssbox/android",
+                crate::guarded![
+                    scope::FeedbackRead => [feedbacks],
+                    scope::FeedbackRssbox => [feedback],
+                    public => [count],
+                ],
+            )
+        };
+        assert!(self_check(guarded()).await);
+
+        assert!(
+            !self_check(
+                guarded().mount_guarded("/rssbox/android", crate::guarded![public => [unguarded]])
+            )
+            .await
+        );
+        assert!(
+            !self_check(
+                guarded()
+                    .mount_guarded("/rssbox/android", crate::guarded![public => [public_post]])
+            )
+            .await
+        );
+
+        // Mounted without the requirements of its handlers.
+        assert!(!self_check(rocket::build().mount("/rssbox/android", routes![feedbacks])).await);
+    }
+
+    #[tokio::test]
+    async fn test_unchecked_scope() -> anyhow::Result<()> {
+        let _mtx = TEST_MTX.lock().await;
+        set_tokens("admin-token", "app-token");
+
+        // A handler without a guard, and one checking another scope.
+        let rocket = rocket::build().attach(SelfCheck).mount_guarded(
+            "/rssbox/android",
+            crate::guarded![
+                scope::FeedbackDelete => [unguarded, feedbacks],
+                scope::FeedbackRssbox => [feedback],
+            ],
+        );
+        let client = Client::tracked(rocket).await?;
+
+        let admin = Some("admin-token");
+        assert_eq!(
+            status(&client, Method::Delete, "/rssbox/android/feedback/1", admin).await,
+            Status::InternalServerError
+        );
+        assert_eq!(
+            status(&client, Method::Get, "/rssbox/android/feedbacks", admin).await,
+            Status::InternalServerError
+        );
+        assert_eq!(
+            status(&client, Method::Post, "/rssbox/android/feedback", admin).await,
+            Status::Ok
+        );
+        Ok(())
+    }
 }
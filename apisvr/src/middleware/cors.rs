@@ -1,6 +1,148 @@
-use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Header;
-use rocket::{Request, Response};
+use crate::{config, response::data};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{ContentType, Header, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::{Build, Request, Response, Rocket};
+
+const ALLOW_ORIGIN: &str = "Access-Control-Allow-Origin";
+const ALLOW_METHODS: &str = "Access-Control-Allow-Methods";
+const ALLOW_HEADERS: &str = "Access-Control-Allow-Headers";
+const ALLOW_CREDENTIALS: &str = "Access-Control-Allow-Credentials";
+const EXPOSE_HEADERS: &str = "Access-Control-Expose-Headers";
+const MAX_AGE: &str = "Access-Control-Max-Age";
+const REQUEST_METHOD: &str = "Access-Control-Request-Method";
+const REQUEST_HEADERS: &str = "Access-Control-Request-Headers";
+
+// The effective CORS settings for a request path, after the mount override.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub max_age: u64,
+    pub credentials: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AllowOrigin {
+    Any,
+    Origin(String),
+}
+
+fn is_under(path: &str, mount: &str) -> bool {
+    let mount = mount.trim_end_matches('/');
+    path == mount || (path.starts_with(mount) && path[mount.len()..].starts_with('/'))
+}
+
+pub fn policy(path: &str) -> Policy {
+    let conf = config::cors();
+    let mut policy = Policy {
+        origins: conf.allowed_origins,
+        methods: conf.allowed_methods,
+        headers: conf.allowed_headers,
+        expose_headers: conf.expose_headers,
+        max_age: conf.max_age,
+        credentials: conf.allow_credentials,
+    };
+
+    if let Some((_, o)) = conf
+        .mounts
+        .iter()
+        .filter(|(mount, _)| is_under(path, mount))
+        .max_by_key(|(mount, _)| mount.len())
+    {
+        let o = o.clone();
+        policy.origins = o.allowed_origins.unwrap_or(policy.origins);
+        policy.methods = o.allowed_methods.unwrap_or(policy.methods);
+        policy.headers = o.allowed_headers.unwrap_or(policy.headers);
+        policy.expose_headers = o.expose_headers.unwrap_or(policy.expose_headers);
+        policy.max_age = o.max_age.unwrap_or(policy.max_age);
+        policy.credentials = o.allow_credentials.unwrap_or(policy.credentials);
+    }
+
+    policy
+}
+
+// `pattern` is an exact origin or `scheme://*.domain[:port]`, which matches
+// any subdomain of `domain` but not `domain` itself.
+pub fn is_origin_match(pattern: &str, origin: &str) -> bool {
+    let (pattern, origin) = (pattern.to_ascii_lowercase(), origin.to_ascii_lowercase());
+    if pattern == origin {
+        return true;
+    }
+
+    let (Some((scheme, host)), Some((o_scheme, o_host))) =
+        (pattern.split_once("://"), origin.split_once("://"))
+    else {
+        return false;
+    };
+
+    match host.strip_prefix("*.") {
+        Some(domain) => {
+            scheme == o_scheme
+                && o_host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+        }
+        None => false,
+    }
+}
+
+fn contains(list: &[String], item: &str) -> bool {
+    list.iter()
+        .any(|v| v == "*" || v.eq_ignore_ascii_case(item))
+}
+
+impl Policy {
+    // An exact or wildcard subdomain match echoes the origin and may carry
+    // credentials, a bare `*` never does.
+    pub fn allow_origin(&self, origin: &str) -> Option<AllowOrigin> {
+        if self
+            .origins
+            .iter()
+            .any(|p| p != "*" && is_origin_match(p, origin))
+        {
+            Some(AllowOrigin::Origin(origin.to_string()))
+        } else if self.origins.iter().any(|p| p == "*") {
+            Some(AllowOrigin::Any)
+        } else {
+            None
+        }
+    }
+
+    pub fn allow_method(&self, method: &str) -> bool {
+        contains(&self.methods, method)
+    }
+
+    // Returns the value of `Access-Control-Allow-Headers`, or None when one of
+    // the requested headers is not allowed.
+    pub fn allow_headers(&self, requested: Option<&str>) -> Option<String> {
+        let requested = requested
+            .unwrap_or_default()
+            .split(',')
+            .map(|h| h.trim())
+            .filter(|h| !h.is_empty())
+            .collect::<Vec<_>>();
+
+        if requested.is_empty() {
+            return Some(
+                self.headers
+                    .iter()
+                    .filter(|h| *h != "*")
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+
+        requested
+            .iter()
+            .all(|h| contains(&self.headers, h))
+            .then(|| requested.join(", "))
+    }
+}
 
 pub struct Cors;
 
@@ -9,17 +151,227 @@ impl Fairing for Cors {
     fn info(&self) -> Info {
         Info {
             name: "Add CORS headers to responses",
-            kind: Kind::Response,
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let conf = config::cors();
+        if conf.allow_credentials && conf.allowed_origins.iter().any(|o| o == "*") {
+            log::warn!("cors: credentials are never sent to the `*` origin, list the origins");
         }
+        Ok(rocket)
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new(
-            "Access-Control-Allow-Methods",
-            "POST, GET, PATCH, OPTIONS",
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+
+        let policy = policy(request.uri().path().as_str());
+        match policy.allow_origin(origin) {
+            None => return,
+            Some(AllowOrigin::Any) => {
+                response.set_header(Header::new(ALLOW_ORIGIN, "*"));
+            }
+            Some(AllowOrigin::Origin(origin)) => {
+                response.set_header(Header::new(ALLOW_ORIGIN, origin));
+                response.set_header(Header::new("Vary", "Origin"));
+                if policy.credentials {
+                    response.set_header(Header::new(ALLOW_CREDENTIALS, "true"));
+                }
+            }
+        }
+
+        if !policy.expose_headers.is_empty() {
+            response.set_header(Header::new(
+                EXPOSE_HEADERS,
+                policy.expose_headers.join(", "),
+            ));
+        }
+    }
+}
+
+pub struct Preflight {
+    path: String,
+    origin: Option<String>,
+    method: Option<String>,
+    headers: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preflight {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(Preflight {
+            path: request.uri().path().to_string(),
+            origin: headers.get_one("Origin").map(String::from),
+            method: headers.get_one(REQUEST_METHOD).map(String::from),
+            headers: headers.get_one(REQUEST_HEADERS).map(String::from),
+        })
+    }
+}
+
+// Answers every OPTIONS request, the CORS origin headers are added by the
+// `Cors` fairing like for any other response.
+#[options("/<_..>")]
+pub fn preflight(req: Preflight) -> data::Data {
+    let policy = policy(&req.path);
+    let ok = data::Data::new_with_status(vec![], ContentType::Plain, Status::NoContent);
+
+    let (Some(origin), Some(method)) = (req.origin, req.method) else {
+        return ok.with_header("Allow", policy.methods.join(", "));
+    };
+
+    if policy.allow_origin(&origin).is_none() {
+        return data::Data::error(Status::Forbidden, "origin is not allowed")
+            .with_details(serde_json::json!({ "origin": origin }));
+    }
+
+    if !policy.allow_method(&method) {
+        return data::Data::error(Status::Forbidden, "method is not allowed")
+            .with_details(serde_json::json!({ "method": method }));
+    }
+
+    match policy.allow_headers(req.headers.as_deref()) {
+        None => data::Data::error(Status::Forbidden, "request headers are not allowed")
+            .with_details(serde_json::json!({ "headers": req.headers })),
+        Some(headers) => ok
+            .with_header(ALLOW_METHODS, policy.methods.join(", "))
+            .with_header(ALLOW_HEADERS, headers)
+            .with_header(MAX_AGE, policy.max_age.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::conf::CONFIG;
+    use rocket::local::blocking::Client;
+    use std::sync::Mutex;
+
+    static MTX: Mutex<()> = Mutex::new(());
+
+    #[delete("/feedback/<_uuid>")]
+    fn delete(_uuid: &str) -> &'static str {
+        "deleted"
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .attach(Cors)
+            .mount("/", routes![preflight])
+            .mount("/admin", routes![delete]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn set_cors(origins: &[&str], credentials: bool, admin_origins: Option<&[&str]>) {
+        let mut config = CONFIG.lock().unwrap();
+        config.cors = Default::default();
+        config.cors.allowed_origins = origins.iter().map(|o| o.to_string()).collect();
+        config.cors.allow_credentials = credentials;
+        if let Some(origins) = admin_origins {
+            config.cors.mounts.insert(
+                "/admin".to_string(),
+                serde_json::from_value(serde_json::json!({ "allowed_origins": origins })).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_origin_match() {
+        assert!(is_origin_match("https://a.com", "https://A.com"));
+        assert!(is_origin_match("https://*.a.com", "https://x.a.com"));
+        assert!(is_origin_match("https://*.a.com", "https://y.x.a.com"));
+        assert!(is_origin_match(
+            "https://*.a.com:8080",
+            "https://x.a.com:8080"
+        ));
+        assert!(!is_origin_match("https://*.a.com", "https://a.com"));
+        assert!(!is_origin_match("https://*.a.com", "https://xa.com"));
+        assert!(!is_origin_match("https://*.a.com", "http://x.a.com"));
+        assert!(!is_origin_match(
+            "https://*.a.com",
+            "https://x.a.com.evil.com"
         ));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+    }
+
+    #[test]
+    fn test_preflight() {
+        let _mtx = MTX.lock().unwrap();
+        set_cors(&["https://*.example.com"], true, None);
+        let client = client();
+
+        let resp = client
+            .options("/admin/feedback/1")
+            .header(Header::new("Origin", "https://tool.example.com"))
+            .header(Header::new(REQUEST_METHOD, "DELETE"))
+            .header(Header::new(REQUEST_HEADERS, "authorization, content-type"))
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get_one(ALLOW_ORIGIN),
+            Some("https://tool.example.com")
+        );
+        assert_eq!(headers.get_one(ALLOW_CREDENTIALS), Some("true"));
+        assert!(headers.get_one(ALLOW_METHODS).unwrap().contains("DELETE"));
+        assert_eq!(
+            headers.get_one(ALLOW_HEADERS),
+            Some("authorization, content-type")
+        );
+        assert_eq!(headers.get_one(MAX_AGE), Some("86400"));
+
+        let resp = client
+            .options("/admin/feedback/1")
+            .header(Header::new("Origin", "https://evil.com"))
+            .header(Header::new(REQUEST_METHOD, "DELETE"))
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert!(resp.headers().get_one(ALLOW_ORIGIN).is_none());
+
+        let resp = client
+            .options("/admin/feedback/1")
+            .header(Header::new("Origin", "https://tool.example.com"))
+            .header(Header::new(REQUEST_METHOD, "DELETE"))
+            .header(Header::new(REQUEST_HEADERS, "x-unknown"))
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_wildcard_and_mount_override() {
+        let _mtx = MTX.lock().unwrap();
+        set_cors(&["*"], true, Some(&["https://admin.example.com"]));
+        let client = client();
+
+        let resp = client
+            .options("/ping")
+            .header(Header::new("Origin", "https://any.com"))
+            .header(Header::new(REQUEST_METHOD, "GET"))
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(resp.headers().get_one(ALLOW_ORIGIN), Some("*"));
+        assert!(resp.headers().get_one(ALLOW_CREDENTIALS).is_none());
+
+        let resp = client
+            .delete("/admin/feedback/1")
+            .header(Header::new("Origin", "https://any.com"))
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        assert!(resp.headers().get_one(ALLOW_ORIGIN).is_none());
+
+        let resp = client
+            .delete("/admin/feedback/1")
+            .header(Header::new("Origin", "https://admin.example.com"))
+            .dispatch();
+        assert_eq!(
+            resp.headers().get_one(ALLOW_ORIGIN),
+            Some("https://admin.example.com")
+        );
+        assert_eq!(resp.headers().get_one(ALLOW_CREDENTIALS), Some("true"));
     }
 }
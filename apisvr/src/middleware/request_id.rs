@@ -0,0 +1,52 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use uuid::Uuid;
+
+pub const HEADER: &str = "X-Request-Id";
+
+pub struct RequestId;
+
+struct Id(String);
+
+// A caller supplied id is reused so a request can be traced across services,
+// anything that does not look like an id is replaced.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn get<'r>(request: &'r Request<'_>) -> &'r str {
+    &request
+        .local_cache(|| {
+            let id = request
+                .headers()
+                .get_one(HEADER)
+                .filter(|id| is_valid(id))
+                .map(|id| id.to_string());
+
+            Id(id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+        })
+        .0
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestId {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tag requests and responses with a request id",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        get(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new(HEADER, get(request).to_string()));
+    }
+}
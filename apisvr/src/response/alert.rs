@@ -0,0 +1,506 @@
+use super::{cryptocurrency::GreedFear, provider::Latest};
+use crate::{
+    conf,
+    db::{
+        self,
+        alert::{Alert, DeliveryStatus, Metric, Op},
+    },
+};
+use chrono::Utc;
+use reqwest::{redirect, Client, Url};
+use rocket::tokio::{self, net::lookup_host, time::Duration};
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+
+// Nothing is delivering yet, every pending delivery is left from the last run.
+pub async fn init() {
+    match db::alert::fail_pending("interrupted by a restart").await {
+        Ok(0) => (),
+        Ok(n) => log::warn!("failed {n} alert deliveries interrupted by a restart"),
+        Err(e) => log::warn!("fail pending alert deliveries error: {e:?}"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Fire,
+    Rearm,
+    Keep,
+}
+
+// A crossed alert fires once and disarms. It re-arms only after the value
+// moved back past `threshold -/+ hysteresis`, and never fires again within
+// `cooldown` seconds of the last trigger.
+pub fn decide(alert: &Alert, value: f64, now: i64) -> Decision {
+    let (is_crossed, is_cleared) = match alert.op {
+        Op::Above => (
+            value > alert.threshold,
+            value <= alert.threshold - alert.hysteresis,
+        ),
+        Op::Below => (
+            value < alert.threshold,
+            value >= alert.threshold + alert.hysteresis,
+        ),
+    };
+
+    if !alert.armed {
+        return if is_cleared {
+            Decision::Rearm
+        } else {
+            Decision::Keep
+        };
+    }
+
+    let is_cooling = alert
+        .last_triggered_at
+        .is_some_and(|t| now - t < alert.cooldown);
+
+    if is_crossed && !is_cooling {
+        Decision::Fire
+    } else {
+        Decision::Keep
+    }
+}
+
+pub async fn evaluate_prices(latest: &Latest) -> db::Result<()> {
+    evaluate(Metric::Price, |alert| {
+        let symbol = alert.symbol.as_deref()?;
+        latest
+            .coins
+            .iter()
+            .find(|c| c.symbol.eq_ignore_ascii_case(symbol))
+            .map(|c| c.price)
+    })
+    .await
+}
+
+// Only the most recent greed/fear index is compared.
+pub async fn evaluate_greed_fear(greed_fear: &GreedFear) -> db::Result<()> {
+    let value = greed_fear
+        .data
+        .first()
+        .and_then(|v| v.value.parse::<f64>().ok());
+
+    evaluate(Metric::GreedFear, |_| value).await
+}
+
+async fn evaluate(metric: Metric, value_of: impl Fn(&Alert) -> Option<f64>) -> db::Result<()> {
+    let now = Utc::now().timestamp();
+
+    for alert in db::alert::select_by_metric(metric).await? {
+        let Some(value) = value_of(&alert) else {
+            continue;
+        };
+
+        match decide(&alert, value, now) {
+            Decision::Fire => {
+                let delivery_id = db::alert::fire(alert.id, now, value).await?;
+                tokio::spawn(async move { deliver(alert, delivery_id, value, now).await });
+            }
+            Decision::Rearm => {
+                db::alert::set_state(alert.id, true, alert.last_triggered_at).await?;
+            }
+            Decision::Keep => (),
+        }
+    }
+
+    Ok(())
+}
+
+// Addresses of the network the server runs in, which a webhook must not reach.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier grade NAT, 100.64.0.0/10.
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link local fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+// A webhook url with the address its host resolved to. Requests connect to
+// that address, so the host can not be pointed elsewhere after the check.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Url,
+    addr: SocketAddr,
+}
+
+// Returns the message for a 400 when the url is not an http(s) url, its host
+// does not resolve or resolves to an internal address.
+pub async fn resolve_webhook(url: &str) -> Result<Webhook, String> {
+    let url = match Url::parse(url) {
+        Ok(v) if matches!(v.scheme(), "http" | "https") => v,
+        _ => return Err("webhook must be an http or https url".to_string()),
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err("webhook must have a host".to_string());
+    };
+
+    let addrs = lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|e| format!("webhook host {host} does not resolve: {e}"))?
+        .collect::<Vec<_>>();
+
+    let is_allowed = conf::alert()
+        .allowed_hosts
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(host));
+    if !is_allowed {
+        if let Some(addr) = addrs.iter().find(|a| is_internal(a.ip())) {
+            return Err(format!(
+                "webhook host {host} resolves to the internal address {}",
+                addr.ip()
+            ));
+        }
+    }
+
+    match addrs.first() {
+        Some(addr) => Ok(Webhook { addr: *addr, url }),
+        None => Err(format!("webhook host {host} does not resolve")),
+    }
+}
+
+pub fn payload(alert: &Alert, value: f64, triggered_at: i64) -> Value {
+    json!({
+        "alert_id": alert.id,
+        "metric": alert.metric,
+        "symbol": alert.symbol,
+        "op": alert.op,
+        "threshold": alert.threshold,
+        "value": value,
+        "triggered_at": triggered_at,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub response_code: Option<i64>,
+    pub error: Option<String>,
+}
+
+// Posts `payload` until the webhook answers with a 2xx or the attempts run
+// out. Redirects are not followed, they could lead to an internal address.
+pub async fn post_with_retries(
+    webhook: &Webhook,
+    payload: &Value,
+    max_attempts: u32,
+    retry_delay: Duration,
+    timeout: Duration,
+) -> Outcome {
+    let mut builder = Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::none());
+    if let Some(domain) = webhook.url.domain() {
+        builder = builder.resolve(domain, webhook.addr);
+    }
+    let client = builder.build().unwrap_or_default();
+    let mut outcome = Outcome {
+        status: DeliveryStatus::Failed,
+        attempts: 0,
+        response_code: None,
+        error: None,
+    };
+
+    for attempt in 0..max_attempts.max(1) {
+        if attempt > 0 {
+            tokio::time::sleep(retry_delay * 2_u32.saturating_pow(attempt - 1)).await;
+        }
+        outcome.attempts += 1;
+
+        match client.post(webhook.url.clone()).json(payload).send().await {
+            Ok(resp) => {
+                outcome.response_code = Some(resp.status().as_u16() as i64);
+                if resp.status().is_success() {
+                    outcome.status = DeliveryStatus::Delivered;
+                    outcome.error = None;
+                    break;
+                }
+                outcome.error = Some(format!("http status {}", resp.status()));
+            }
+            Err(e) => {
+                outcome.response_code = None;
+                outcome.error = Some(e.to_string());
+            }
+        }
+    }
+
+    outcome
+}
+
+async fn deliver(alert: Alert, delivery_id: i64, value: f64, triggered_at: i64) {
+    let conf = conf::alert();
+
+    // Resolved again, the host may point elsewhere since the alert was saved.
+    let outcome = match resolve_webhook(&alert.webhook).await {
+        Ok(webhook) => {
+            post_with_retries(
+                &webhook,
+                &payload(&alert, value, triggered_at),
+                conf.max_attempts,
+                Duration::from_millis(conf.retry_delay_ms),
+                Duration::from_secs(conf.timeout_secs),
+            )
+            .await
+        }
+        Err(e) => Outcome {
+            status: DeliveryStatus::Failed,
+            attempts: 0,
+            response_code: None,
+            error: Some(e),
+        },
+    };
+
+    if outcome.status == DeliveryStatus::Failed {
+        log::warn!(
+            "deliver alert {} failed after {} attempts: {:?}",
+            alert.id,
+            outcome.attempts,
+            outcome.error
+        );
+    }
+
+    if let Err(e) = db::alert::finish_delivery(
+        delivery_id,
+        outcome.status,
+        outcome.attempts,
+        outcome.response_code,
+        outcome.error.as_deref(),
+    )
+    .await
+    {
+        log::warn!("record alert delivery error: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::mock;
+
+    fn alert(op: Op, armed: bool, last_triggered_at: Option<i64>) -> Alert {
+        Alert {
+            id: 1,
+            token_id: 1,
+            metric: Metric::Price,
+            symbol: Some("BTC".to_string()),
+            op,
+            threshold: 100.0,
+            hysteresis: 10.0,
+            cooldown: 60,
+            webhook: String::default(),
+            armed,
+            last_triggered_at,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let now = 1000;
+
+        assert_eq!(
+            decide(&alert(Op::Above, true, None), 101.0, now),
+            Decision::Fire
+        );
+        assert_eq!(
+            decide(&alert(Op::Above, true, None), 100.0, now),
+            Decision::Keep
+        );
+        assert_eq!(
+            decide(&alert(Op::Below, true, None), 99.0, now),
+            Decision::Fire
+        );
+
+        // Disarmed alerts need to leave the hysteresis band first.
+        assert_eq!(
+            decide(&alert(Op::Above, false, Some(0)), 95.0, now),
+            Decision::Keep
+        );
+        assert_eq!(
+            decide(&alert(Op::Above, false, Some(0)), 90.0, now),
+            Decision::Rearm
+        );
+        assert_eq!(
+            decide(&alert(Op::Below, false, Some(0)), 110.0, now),
+            Decision::Rearm
+        );
+
+        // Re-armed but still cooling down.
+        assert_eq!(
+            decide(&alert(Op::Above, true, Some(now - 30)), 101.0, now),
+            Decision::Keep
+        );
+        assert_eq!(
+            decide(&alert(Op::Above, true, Some(now - 60)), 101.0, now),
+            Decision::Fire
+        );
+    }
+
+    // The mock server listens on loopback, which `resolve_webhook` refuses.
+    fn mock_webhook(url: &str) -> Webhook {
+        let url = Url::parse(url).unwrap();
+        Webhook {
+            addr: url.socket_addrs(|| None).unwrap()[0],
+            url,
+        }
+    }
+
+    #[test]
+    fn test_is_internal() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["203.0.113.10", "8.8.8.8", "2001:db8::1"] {
+            assert!(!is_internal(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[rocket::tokio::test]
+    async fn test_resolve_webhook() {
+        let webhook = resolve_webhook("https://203.0.113.10/hook").await.unwrap();
+        assert_eq!(webhook.addr, "203.0.113.10:443".parse().unwrap());
+
+        for url in [
+            "ftp://203.0.113.10/hook",
+            "http://127.0.0.2:8080/hook",
+            "http://localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:10.0.0.1]/hook",
+        ] {
+            assert!(resolve_webhook(url).await.is_err(), "{url}");
+        }
+    }
+
+    #[rocket::tokio::test]
+    async fn test_post_with_retries() {
+        let server = mock::Server::start().await;
+        server.route("/ok", 200, "{}").route("/fail", 500, "{}");
+        let payload = payload(&alert(Op::Above, true, None), 101.0, 1000);
+        let (delay, timeout) = (Duration::from_millis(1), Duration::from_secs(5));
+
+        let outcome = post_with_retries(
+            &mock_webhook(&format!("{}/ok", server.base_url)),
+            &payload,
+            3,
+            delay,
+            timeout,
+        )
+        .await;
+        assert_eq!(outcome.status, DeliveryStatus::Delivered);
+        assert_eq!(outcome.attempts, 1);
+        assert_eq!(outcome.response_code, Some(200));
+
+        let outcome = post_with_retries(
+            &mock_webhook(&format!("{}/fail", server.base_url)),
+            &payload,
+            3,
+            delay,
+            timeout,
+        )
+        .await;
+        assert_eq!(outcome.status, DeliveryStatus::Failed);
+        assert_eq!(outcome.attempts, 3);
+        assert_eq!(outcome.response_code, Some(500));
+        assert_eq!(server.hits("/fail"), 3);
+    }
+
+    #[rocket::tokio::test]
+    async fn test_evaluate_prices() -> anyhow::Result<()> {
+        let _mtx = db::TEST_MTX.lock().await;
+        let _ = std::fs::remove_file("/tmp/alert-eval-test.db");
+        db::init("/tmp/alert-eval-test.db").await;
+
+        let server = mock::Server::start().await;
+        server.route("/hook", 200, "{}");
+        crate::config::conf::CONFIG
+            .lock()
+            .unwrap()
+            .alert
+            .allowed_hosts = vec!["127.0.0.1".to_string()];
+        let a = db::alert::create(
+            1,
+            &db::alert::AlertSpec {
+                metric: Metric::Price,
+                symbol: Some("BTC".to_string()),
+                op: Op::Above,
+                threshold: 100.0,
+                hysteresis: 10.0,
+                cooldown: 0,
+                webhook: format!("{}/hook", server.base_url),
+            },
+        )
+        .await?;
+
+        let latest = |price| Latest {
+            coins: vec![crate::response::provider::Coin {
+                symbol: "BTC".to_string(),
+                price,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        for price in [101.0, 105.0, 95.0, 89.0, 102.0] {
+            evaluate_prices(&latest(price)).await?;
+        }
+
+        let mut deliveries = vec![];
+        for _ in 0..100 {
+            deliveries = db::alert::select_deliveries(a.id, 10).await?;
+            if deliveries
+                .iter()
+                .all(|d| d.status != DeliveryStatus::Pending)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(server.hits("/hook"), 2);
+        assert_eq!(
+            deliveries.iter().map(|d| d.value).collect::<Vec<_>>(),
+            vec![102.0, 101.0]
+        );
+        assert!(deliveries
+            .iter()
+            .all(|d| d.status == DeliveryStatus::Delivered));
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{conf, db};
+use anyhow::Result;
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::BTreeMap, sync::Mutex};
+
+lazy_static! {
+    static ref STATUS: Mutex<BTreeMap<String, Status>> = Mutex::new(BTreeMap::new());
+}
+
+// How the last refreshes of a cache entry went. A failed refresh leaves the
+// cached value alone, so `error` explains why it may be getting old.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Status {
+    pub name: String,
+    pub fetched_at: Option<i64>,
+    pub source: Option<String>,
+
+    // The value was restored from a snapshot and not refreshed since.
+    pub stale: bool,
+    pub failed_at: Option<i64>,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn update(name: &str, f: impl FnOnce(&mut Status)) {
+    let mut status = STATUS.lock().unwrap();
+    f(status.entry(name.to_string()).or_insert_with(|| Status {
+        name: name.to_string(),
+        ..Default::default()
+    }));
+}
+
+// Records the outcome of fetching the cache entry `name` and passes it on. A
+// good value is snapshotted so that a restart can serve it right away.
+pub async fn checked<T: Serialize>(name: &str, source: &str, result: Result<T>) -> Result<T> {
+    let now = Utc::now().timestamp();
+    update(name, |s| match &result {
+        Ok(_) => {
+            s.fetched_at = Some(now);
+            s.source = Some(source.to_string());
+            s.stale = false;
+            s.consecutive_failures = 0;
+        }
+        Err(e) => {
+            s.failed_at = Some(now);
+            s.consecutive_failures += 1;
+            s.error = Some(format!("{e:#}"));
+        }
+    });
+
+    if let Ok(v) = &result {
+        match serde_json::to_string(v) {
+            Ok(data) => {
+                if let Err(e) = db::cache::save(name, &data, now, source).await {
+                    log::warn!("save {name} cache snapshot error: {e:?}");
+                }
+            }
+            Err(e) => log::warn!("serialize {name} cache snapshot error: {e:?}"),
+        }
+    }
+    result
+}
+
+// The snapshot of `name` saved by an earlier run, stale until the first
+// refresh succeeds.
+pub async fn restore<T: DeserializeOwned>(name: &str) -> Option<T> {
+    let snapshot = match db::cache::select(name).await {
+        Ok(v) => v,
+        Err(db::Error::RowNotFound) => return None,
+        Err(e) => {
+            log::warn!("load {name} cache snapshot error: {e:?}");
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&snapshot.data) {
+        Ok(v) => {
+            update(name, |s| {
+                s.fetched_at = Some(snapshot.fetched_at);
+                s.source = Some(snapshot.source.clone()).filter(|v| !v.is_empty());
+                s.stale = true;
+            });
+            Some(v)
+        }
+        Err(e) => {
+            log::warn!("parse {name} cache snapshot error: {e:?}");
+            None
+        }
+    }
+}
+
+// How old a cached value is. It is stale once it is older than the
+// configured threshold of its entry, or while it is still the snapshot of an
+// earlier run.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Freshness {
+    pub fetched_at: Option<i64>,
+    pub source: Option<String>,
+    pub age_seconds: Option<i64>,
+    pub stale: bool,
+}
+
+impl Freshness {
+    // A value fetched just now that is served without being cached.
+    pub fn now(source: &str) -> Self {
+        Self {
+            fetched_at: Some(Utc::now().timestamp()),
+            source: Some(source.to_string()),
+            age_seconds: Some(0),
+            stale: false,
+        }
+    }
+
+    pub fn from_status(status: Option<&Status>, threshold: i64, now: i64) -> Self {
+        let Some(status) = status else {
+            return Self {
+                stale: true,
+                ..Default::default()
+            };
+        };
+
+        let age = status.fetched_at.map(|t| (now - t).max(0));
+        Self {
+            fetched_at: status.fetched_at,
+            source: status.source.clone(),
+            age_seconds: age,
+            stale: status.stale || age.is_none_or(|v| v > threshold),
+        }
+    }
+}
+
+// A cached value together with its freshness.
+#[derive(Serialize, Debug, Clone)]
+pub struct Fresh<T> {
+    #[serde(flatten)]
+    pub freshness: Freshness,
+    pub data: T,
+}
+
+impl<T: Serialize> Fresh<T> {
+    pub fn new(name: &str, data: T) -> Self {
+        Self {
+            freshness: freshness(name),
+            data,
+        }
+    }
+
+    // What the ETag is computed from, `age_seconds` is left out as it changes
+    // every second while the value does not.
+    pub fn etag_content(&self) -> Vec<u8> {
+        let f = &self.freshness;
+        serde_json::to_vec(&(f.fetched_at, &f.source, f.stale, &self.data)).unwrap_or_default()
+    }
+}
+
+pub fn freshness(name: &str) -> Freshness {
+    freshness_within(name, conf::freshness().threshold(name))
+}
+
+pub fn freshness_within(name: &str, threshold: i64) -> Freshness {
+    Freshness::from_status(
+        STATUS.lock().unwrap().get(name),
+        threshold,
+        Utc::now().timestamp(),
+    )
+}
+
+pub fn status(name: &str) -> Option<Status> {
+    STATUS.lock().unwrap().get(name).cloned()
+}
+
+pub fn statuses() -> Vec<Status> {
+    STATUS.lock().unwrap().values().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use rocket::tokio;
+
+    fn status(name: &str) -> Status {
+        statuses().into_iter().find(|s| s.name == name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_checked_restore() {
+        let _mtx = db::TEST_MTX.lock().await;
+        let _ = std::fs::remove_file("/tmp/cache-status-test.db");
+        db::init("/tmp/cache-status-test.db").await;
+
+        assert_eq!(restore::<Vec<i32>>("test").await, None);
+        assert_eq!(
+            checked("test", "mock", Ok(vec![1, 2])).await.unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(status("test").consecutive_failures, 0);
+
+        let e = checked::<Vec<i32>>("test", "mock", Err(anyhow!("http status 401")))
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "http status 401");
+
+        let s = status("test");
+        assert_eq!(s.consecutive_failures, 1);
+        assert_eq!(s.error.as_deref(), Some("http status 401"));
+
+        // The failed refresh kept the last good snapshot.
+        assert_eq!(restore::<Vec<i32>>("test").await, Some(vec![1, 2]));
+        let s = status("test");
+        assert!(s.stale);
+        assert!(s.fetched_at.is_some());
+        assert_eq!(s.source.as_deref(), Some("mock"));
+
+        checked("test", "mock", Ok(vec![3])).await.unwrap();
+        assert!(!status("test").stale);
+    }
+
+    #[test]
+    fn test_freshness() {
+        let status = Status {
+            fetched_at: Some(100),
+            source: Some("mock".to_string()),
+            ..Default::default()
+        };
+
+        let f = Freshness::from_status(Some(&status), 60, 130);
+        assert_eq!((f.age_seconds, f.stale), (Some(30), false));
+        assert_eq!(f.source.as_deref(), Some("mock"));
+
+        let f = Freshness::from_status(Some(&status), 60, 161);
+        assert_eq!((f.age_seconds, f.stale), (Some(61), true));
+
+        // A restored snapshot is stale however young it is.
+        let restored = Status {
+            stale: true,
+            ..status
+        };
+        assert!(Freshness::from_status(Some(&restored), 60, 100).stale);
+
+        let f = Freshness::from_status(None, 60, 100);
+        assert_eq!((f.fetched_at, f.stale), (None, true));
+    }
+}
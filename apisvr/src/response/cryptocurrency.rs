@@ -1,17 +1,27 @@
-use super::RUNTIME;
-use crate::conf;
-use anyhow::{anyhow, Result};
-use reqwest::{
-    header::{HeaderMap, ACCEPT},
-    Client, Proxy,
+pub use super::provider::Coin;
+use super::{
+    alert,
+    cache::{self, Fresh, Freshness},
+    fx::Rate,
+    history,
+    provider::{self, Latest},
+    stream::{self, Topic},
+    upstream,
 };
-use rocket::tokio::{self, sync::Mutex, time::Duration};
+use crate::{
+    conf,
+    scheduler::{self, Job},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, SecondsFormat};
+use reqwest::header::ACCEPT;
+use rocket::tokio::sync::Mutex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::{cmp::Ordering, collections::HashMap};
 
 lazy_static! {
-    static ref LATEST: Mutex<Option<String>> = Mutex::new(None);
+    static ref LATEST: Mutex<Option<Latest>> = Mutex::new(None);
     static ref STATS: Mutex<Stats> = Mutex::new(Stats::default());
 }
 
@@ -38,6 +48,48 @@ pub struct Global {
     pub total_24h_volume_usd: u64,
     pub bitcoin_percentage_of_market_cap: f64,
     pub last_updated: i64,
+
+    // The totals in the requested currency, only set on conversion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_market_cap: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_24h_volume: Option<f64>,
+}
+
+impl GreedFear {
+    // The index is a number from 0 to 100.
+    pub fn validate(&self) -> Result<()> {
+        if self.data.is_empty() {
+            bail!("empty greed fear index");
+        }
+
+        for d in &self.data {
+            match d.value.parse::<u8>() {
+                Ok(v) if v <= 100 && d.timestamp.parse::<i64>().is_ok_and(|t| t > 0) => (),
+                _ => bail!("invalid greed fear index {} at {}", d.value, d.timestamp),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Global {
+    pub fn convert(&mut self, rate: &Rate) {
+        self.total_market_cap = Some(rate.convert(self.total_market_cap_usd as f64));
+        self.total_24h_volume = Some(rate.convert(self.total_24h_volume_usd as f64));
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.total_market_cap_usd == 0 || self.total_24h_volume_usd == 0 {
+            bail!("zero total market cap or volume");
+        }
+
+        let dominance = self.bitcoin_percentage_of_market_cap;
+        if !(dominance > 0.0 && dominance <= 100.0) {
+            bail!("invalid bitcoin dominance {dominance}");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -46,7 +98,7 @@ pub struct GasFee {
     pub bitcoin: (u64, u64, u64),
 }
 
-pub async fn latest_cache() -> Option<String> {
+pub async fn latest_cache() -> Option<Latest> {
     LATEST.lock().await.clone()
 }
 
@@ -54,168 +106,488 @@ pub async fn stats_cache() -> Result<String> {
     Ok(serde_json::to_string(&*STATS.lock().await)?)
 }
 
-pub fn init() {
-    timer();
+// `Stats` with the freshness of every field, they are refreshed by different
+// jobs from different upstreams.
+#[derive(Serialize, Debug, Clone)]
+pub struct FreshStats {
+    pub greed_fear: Fresh<GreedFear>,
+    pub global: Fresh<Global>,
+    pub gas_fee: FreshGasFee,
 }
 
-fn timer() {
-    RUNTIME.lock().unwrap().spawn(async move {
-        log::debug!("timer start...");
+#[derive(Serialize, Debug, Clone)]
+pub struct FreshGasFee {
+    pub ethereum: Fresh<u64>,
+    pub bitcoin: Fresh<(u64, u64, u64)>,
+}
 
-        let mut count = 0_u64;
-        let latest_interval = u64::max(10, conf::timer().coinmarketcap_latest);
+impl FreshStats {
+    // The most recently refreshed field, it dates the stats as a whole.
+    pub fn newest(&self) -> &Freshness {
+        [
+            &self.greed_fear.freshness,
+            &self.global.freshness,
+            &self.gas_fee.ethereum.freshness,
+            &self.gas_fee.bitcoin.freshness,
+        ]
+        .into_iter()
+        .max_by_key(|f| f.fetched_at)
+        .unwrap()
+    }
 
-        loop {
-            if count % latest_interval == 0 {
-                match fetch_latest().await {
-                    Ok(v) => *LATEST.lock().await = Some(v),
-                    Err(e) => log::warn!("fetch_latest error: {e:?}"),
-                }
-            }
+    pub fn etag_content(&self) -> Vec<u8> {
+        [
+            self.greed_fear.etag_content(),
+            self.global.etag_content(),
+            self.gas_fee.ethereum.etag_content(),
+            self.gas_fee.bitcoin.etag_content(),
+        ]
+        .concat()
+    }
+}
 
-            if count % 60 == 0 {
-                match fetch_greed_fear().await {
-                    Ok(v) => STATS.lock().await.greed_fear = v,
-                    Err(e) => log::warn!("fetch_greed_fear error: {e:?}"),
-                }
+// The jobs refreshing the stats, the first one due dates them all.
+pub const STATS_JOBS: &[&str] = &["greed_fear", "global", "eth_gas", "btc_gas"];
 
-                match fetch_global().await {
-                    Ok(v) => STATS.lock().await.global = v,
-                    Err(e) => log::warn!("fetch_global error: {e:?}"),
-                }
-            }
+pub async fn fresh_stats(rate: Option<&Rate>) -> FreshStats {
+    let mut stats = STATS.lock().await.clone();
+    if let Some(rate) = rate {
+        stats.global.convert(rate);
+    }
+    FreshStats {
+        greed_fear: Fresh::new("greed_fear", stats.greed_fear),
+        global: Fresh::new("global", stats.global),
+        gas_fee: FreshGasFee {
+            ethereum: Fresh::new("eth_gas", stats.gas_fee.ethereum),
+            bitcoin: Fresh::new("btc_gas", stats.gas_fee.bitcoin),
+        },
+    }
+}
 
-            if count % 30 == 0 {
-                match fetch_ethereum_gas_fee().await {
-                    Ok(v) => STATS.lock().await.gas_fee.ethereum = v,
-                    Err(e) => log::warn!("fetch_ethereum_gas_fee error: {e:?}"),
-                }
+pub fn latest_interval() -> u64 {
+    u64::max(10, conf::timer().coinmarketcap_latest)
+}
 
-                match fetch_bitcoin_gas_fee().await {
-                    Ok(v) => STATS.lock().await.gas_fee.bitcoin = v,
-                    Err(e) => log::warn!("fetch_bitcoin_gas_fee error: {e:?}"),
-                }
-            }
+// Serves the snapshots of the last run until the jobs refreshed them. A
+// listing snapshot newer than the refresh interval is not fetched again
+// before it is due.
+pub async fn init() {
+    restore().await;
+    let latest_interval = latest_interval();
+    let tiers = history::tiers();
+    for t in conf::history().tiers.iter().filter(|t| !tiers.contains(t)) {
+        log::warn!(
+            "history tier of {}s is finer than the {latest_interval}s latest refresh, ignored",
+            t.resolution
+        );
+    }
 
-            tokio::time::sleep(Duration::from_secs(1)).await;
-            count += 1;
+    // The listing may fail over through every provider.
+    let latest_timeout = upstream::job_timeout_secs(provider::providers().len());
+    let timeout = upstream::job_timeout_secs(1);
+
+    let mut latest =
+        Job::every("latest", latest_interval, refresh_latest).with_timeout(latest_timeout);
+    if let Some(v) = LATEST.lock().await.as_ref() {
+        if let Some(at) = DateTime::from_timestamp(v.fetched_at, 0) {
+            latest = latest.with_last_run(at);
         }
-    });
+    }
+    scheduler::spawn(latest);
+    scheduler::spawn(Job::every("greed_fear", 60, refresh_greed_fear).with_timeout(timeout));
+    scheduler::spawn(Job::every("global", 60, refresh_global).with_timeout(timeout));
+    scheduler::spawn(Job::every("eth_gas", 30, refresh_ethereum_gas_fee).with_timeout(timeout));
+    scheduler::spawn(Job::every("btc_gas", 30, refresh_bitcoin_gas_fee).with_timeout(timeout));
 }
 
-pub async fn fetch_latest() -> Result<String> {
-    let (socket5, api_key) = (conf::socket5(), conf::api_key().coinmarketcap);
+async fn restore() {
+    if let Some(v) = cache::restore::<Latest>("latest").await {
+        publish_latest(&v);
+        *LATEST.lock().await = Some(v);
+    }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, "application/json".parse().unwrap());
-    headers.insert("X-CMC_PRO_API_KEY", api_key.parse().unwrap());
+    let mut stats = STATS.lock().await;
+    if let Some(v) = cache::restore("greed_fear").await {
+        stats.greed_fear = v;
+    }
+    if let Some(v) = cache::restore("global").await {
+        stats.global = v;
+    }
+    if let Some(v) = cache::restore("eth_gas").await {
+        stats.gas_fee.ethereum = v;
+    }
+    if let Some(v) = cache::restore("btc_gas").await {
+        stats.gas_fee.bitcoin = v;
+    }
+    drop(stats);
 
-    const API: &str = "https://pro-api.coinmarketcap.com/v1/cryptocurrency/listings/latest";
+    if let Err(e) = publish_stats().await {
+        log::warn!("publish stats error: {e:?}");
+    }
+}
 
-    let client = if socket5.coinmarketcap {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
-    } else {
-        Client::new()
-    };
+async fn refresh_latest() -> Result<()> {
+    let result = fetch_latest().await;
+    let source = result
+        .as_ref()
+        .map(|v| v.source.clone())
+        .unwrap_or_default();
+    let v = cache::checked("latest", &source, result).await?;
+    if let Err(e) = history::record(&v).await {
+        log::warn!("record price history error: {e:?}");
+    }
+    if let Err(e) = alert::evaluate_prices(&v).await {
+        log::warn!("evaluate price alerts error: {e:?}");
+    }
+    publish_latest(&v);
+    *LATEST.lock().await = Some(v);
+    Ok(())
+}
 
-    let resp = client
-        .get(API)
-        .headers(headers)
-        .query(&[
-            ("start", "1"),
-            ("limit", "100"),
-            ("convert", "USD"),
-            ("aux", "cmc_rank"),
-        ])
-        .send()
-        .await?
-        .text()
-        .await?;
+async fn refresh_greed_fear() -> Result<()> {
+    let v = cache::checked("greed_fear", "alternative", fetch_greed_fear().await).await?;
+    if let Err(e) = alert::evaluate_greed_fear(&v).await {
+        log::warn!("evaluate greed fear alerts error: {e:?}");
+    }
+    STATS.lock().await.greed_fear = v;
+    publish_stats().await
+}
 
-    Ok(resp)
+async fn refresh_global() -> Result<()> {
+    let v = cache::checked("global", "alternative", fetch_global().await).await?;
+    STATS.lock().await.global = v;
+    publish_stats().await
 }
 
-async fn fetch_greed_fear() -> Result<GreedFear> {
-    let socket5 = conf::socket5();
+async fn refresh_ethereum_gas_fee() -> Result<()> {
+    let v = cache::checked("eth_gas", "etherscan", fetch_ethereum_gas_fee().await).await?;
+    STATS.lock().await.gas_fee.ethereum = v;
+    publish_stats().await
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, "application/json".parse().unwrap());
+async fn refresh_bitcoin_gas_fee() -> Result<()> {
+    let v = cache::checked("btc_gas", "blockstream", fetch_bitcoin_gas_fee().await).await?;
+    STATS.lock().await.gas_fee.bitcoin = v;
+    publish_stats().await
+}
 
-    const API: &str = "https://api.alternative.me/fng/";
+async fn publish_stats() -> Result<()> {
+    stream::publish(Topic::Stats, stats_cache().await?);
+    Ok(())
+}
 
-    let client = if socket5.alternative {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
-    } else {
-        Client::new()
-    };
+// `fetched_at` is left out so that a refresh with the same prices is no change.
+fn publish_latest(latest: &Latest) {
+    let v = json!({ "source": latest.source, "data": latest.coins });
+    stream::publish(Topic::Latest, v.to_string());
+}
 
-    let resp = client
-        .get(API)
-        .headers(headers)
-        .query(&[("limit", "2")])
-        .send()
-        .await?
-        .json()
-        .await?;
+pub async fn fetch_latest() -> Result<Latest> {
+    provider::fetch_latest(&provider::providers(), conf::price().limit).await
+}
 
-    Ok(resp)
+// The CoinMarketCap listing layout the apps were built against, filled from
+// whichever provider answered.
+pub fn listing(latest: &Latest) -> Value {
+    let data = latest
+        .coins
+        .iter()
+        .map(|c| {
+            json!({
+                "name": c.name,
+                "symbol": c.symbol,
+                "cmc_rank": c.rank,
+                "last_updated": timestamp_rfc3339(c.last_updated),
+                "quote": {
+                    "USD": {
+                        "price": c.price,
+                        "volume_24h": c.volume_24h,
+                        "market_cap": c.market_cap,
+                        "percent_change_1h": c.percent_change_1h,
+                        "percent_change_24h": c.percent_change_24h,
+                        "percent_change_7d": c.percent_change_7d,
+                        "last_updated": timestamp_rfc3339(c.last_updated),
+                    }
+                },
+                "circulating_supply": c.circulating_supply,
+                "total_supply": c.total_supply,
+                "max_supply": c.max_supply,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "status": {
+            "timestamp": timestamp_rfc3339(latest.fetched_at),
+            "error_code": 0,
+            "error_message": null,
+            "source": latest.source,
+        },
+        "data": data,
+    })
 }
 
-async fn fetch_global() -> Result<Global> {
-    let socket5 = conf::socket5();
+fn timestamp_rfc3339(ts: i64) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, "application/json".parse().unwrap());
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSort {
+    Rank,
+    Symbol,
+    Name,
+    Price,
+    #[field(value = "market_cap")]
+    MarketCap,
+    #[field(value = "volume_24h")]
+    Volume24h,
+    #[field(value = "percent_change_24h")]
+    PercentChange24h,
+    #[field(value = "percent_change_7d")]
+    PercentChange7d,
+}
 
-    const API: &str = "https://api.alternative.me/v1/global/";
+pub const COIN_FIELDS: &[&str] = &[
+    "symbol",
+    "name",
+    "rank",
+    "price",
+    "market_cap",
+    "volume_24h",
+    "percent_change_1h",
+    "percent_change_24h",
+    "percent_change_7d",
+    "circulating_supply",
+    "total_supply",
+    "max_supply",
+    "last_updated",
+];
+
+// Coins without a value for the sort key always go last.
+fn sort_key(c: &Coin, sort: CoinSort) -> Option<f64> {
+    match sort {
+        CoinSort::Rank => Some(c.rank as f64),
+        CoinSort::Price => Some(c.price),
+        CoinSort::MarketCap => Some(c.market_cap),
+        CoinSort::Volume24h => Some(c.volume_24h),
+        CoinSort::PercentChange24h => c.percent_change_24h,
+        CoinSort::PercentChange7d => c.percent_change_7d,
+        CoinSort::Symbol | CoinSort::Name => None,
+    }
+}
+
+pub fn select_coins(
+    coins: &[Coin],
+    symbols: &[String],
+    sort: CoinSort,
+    is_desc: bool,
+    limit: usize,
+) -> Vec<Coin> {
+    let mut coins = coins
+        .iter()
+        .filter(|c| symbols.is_empty() || symbols.iter().any(|s| s.eq_ignore_ascii_case(&c.symbol)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    coins.sort_by(|a, b| {
+        let ordering = match sort {
+            CoinSort::Symbol => a.symbol.cmp(&b.symbol),
+            CoinSort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            _ => match (sort_key(a, sort), sort_key(b, sort)) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        };
+
+        if is_desc {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    coins.truncate(limit);
+    coins
+}
 
-    let client = if socket5.alternative {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
+// Prices, market caps and volumes in the currency of `rate`.
+pub fn convert_coins(coins: &mut [Coin], rate: &Rate) {
+    for c in coins {
+        c.price = rate.convert(c.price);
+        c.market_cap = rate.convert(c.market_cap);
+        c.volume_24h = rate.convert(c.volume_24h);
+    }
+}
+
+// Keeps only `fields` of each coin, every field when `fields` is empty.
+pub fn project_coins(coins: &[Coin], fields: &[&str]) -> Vec<Value> {
+    coins
+        .iter()
+        .map(|c| {
+            let mut v = serde_json::to_value(c).unwrap_or_default();
+            if let Some(m) = v.as_object_mut().filter(|_| !fields.is_empty()) {
+                m.retain(|k, _| fields.contains(&k.as_str()));
+            }
+            v
+        })
+        .collect()
+}
+
+// `cost_basis` is what the whole amount cost, in the currency valued in.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Holding {
+    pub symbol: String,
+    pub amount: f64,
+    #[serde(default)]
+    pub cost_basis: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub symbol: String,
+    pub name: String,
+    pub amount: f64,
+    pub price: f64,
+    pub value: f64,
+    pub cost_basis: Option<f64>,
+    pub pnl: Option<f64>,
+    pub pnl_percent: Option<f64>,
+
+    // Percent of `total_value`.
+    pub allocation: f64,
+}
+
+// Totals over the priced assets, the cost and PnL over those with a cost basis.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Portfolio {
+    pub assets: Vec<Asset>,
+    pub total_value: f64,
+    pub total_cost: Option<f64>,
+    pub total_pnl: Option<f64>,
+    pub total_pnl_percent: Option<f64>,
+
+    // Symbols missing from the listing or without a price.
+    pub unpriced: Vec<String>,
+}
+
+fn pnl_percent(pnl: f64, cost: f64) -> Option<f64> {
+    (cost > 0.0).then(|| pnl / cost * 100.0)
+}
+
+// Values `holdings` at the listed prices, in the currency of `rate` if any.
+// A symbol listed more than once is priced by its first coin.
+pub fn value_portfolio(coins: &[Coin], holdings: &[Holding], rate: Option<&Rate>) -> Portfolio {
+    let mut assets = vec![];
+    let mut unpriced = vec![];
+
+    for h in holdings {
+        let coin = coins
+            .iter()
+            .find(|c| c.symbol.eq_ignore_ascii_case(&h.symbol))
+            .filter(|c| c.price.is_finite() && c.price > 0.0);
+        let Some(coin) = coin else {
+            unpriced.push(h.symbol.clone());
+            continue;
+        };
+
+        let price = rate.map_or(coin.price, |r| r.convert(coin.price));
+        let value = price * h.amount;
+        let pnl = h.cost_basis.map(|cost| value - cost);
+        assets.push(Asset {
+            symbol: h.symbol.clone(),
+            name: coin.name.clone(),
+            amount: h.amount,
+            price,
+            value,
+            cost_basis: h.cost_basis,
+            pnl,
+            pnl_percent: pnl.zip(h.cost_basis).and_then(|(p, c)| pnl_percent(p, c)),
+            allocation: 0.0,
+        });
+    }
+
+    let total_value = assets.iter().map(|a| a.value).sum::<f64>();
+    if total_value > 0.0 {
+        for a in &mut assets {
+            a.allocation = a.value / total_value * 100.0;
+        }
+    }
+
+    let costed = assets
+        .iter()
+        .filter_map(|a| Some((a.cost_basis?, a.pnl?)))
+        .collect::<Vec<_>>();
+    let (total_cost, total_pnl) = if costed.is_empty() {
+        (None, None)
     } else {
-        Client::new()
+        (
+            Some(costed.iter().map(|(c, _)| c).sum::<f64>()),
+            Some(costed.iter().map(|(_, p)| p).sum::<f64>()),
+        )
     };
 
-    let resp = client
-        .get(API)
-        .headers(headers)
-        .send()
-        .await?
-        .json()
-        .await?;
+    Portfolio {
+        assets,
+        total_value,
+        total_cost,
+        total_pnl,
+        total_pnl_percent: total_pnl
+            .zip(total_cost)
+            .and_then(|(p, c)| pnl_percent(p, c)),
+        unpriced,
+    }
+}
 
-    Ok(resp)
+async fn fetch_greed_fear() -> Result<GreedFear> {
+    const API: &str = "https://api.alternative.me/fng/";
+
+    let resp = upstream::send("alternative", conf::socket5().alternative, |c| {
+        c.get(API)
+            .header(ACCEPT, "application/json")
+            .query(&[("limit", "2")])
+    })
+    .await?;
+
+    let v = upstream::json::<GreedFear>(resp).await?;
+    v.validate()?;
+    Ok(v)
 }
 
-async fn fetch_ethereum_gas_fee() -> Result<u64> {
-    let socket5 = conf::socket5();
+async fn fetch_global() -> Result<Global> {
+    const API: &str = "https://api.alternative.me/v1/global/";
 
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, "application/json".parse().unwrap());
+    let resp = upstream::send("alternative", conf::socket5().alternative, |c| {
+        c.get(API).header(ACCEPT, "application/json")
+    })
+    .await?;
 
+    let v = upstream::json::<Global>(resp).await?;
+    v.validate()?;
+    Ok(v)
+}
+
+async fn fetch_ethereum_gas_fee() -> Result<u64> {
     const API: &str = "https://api.etherscan.io/api?module=proxy&action=eth_gasPrice";
 
-    let client = if socket5.ethscan {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
-    } else {
-        Client::new()
-    };
+    let resp = upstream::send("etherscan", conf::socket5().ethscan, |c| {
+        c.get(API).header(ACCEPT, "application/json")
+    })
+    .await?;
 
-    let resp = client
-        .get(API)
-        .headers(headers)
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
+    parse_ethereum_gas_fee(&upstream::json::<Value>(resp).await?)
+}
 
+// Etherscan reports errors in `result` too, e.g. `Invalid API Key`.
+fn parse_ethereum_gas_fee(resp: &Value) -> Result<u64> {
     match resp.get("result") {
         Some(v) => {
             let v = v.as_str().unwrap_or_default().trim_start_matches("0x");
             match u64::from_str_radix(v, 16) {
+                Ok(0) => Err(anyhow!("zero gas price")),
                 Ok(v) => Ok(v),
                 Err(_) => Err(anyhow!("{v}")),
             }
@@ -226,27 +598,20 @@ async fn fetch_ethereum_gas_fee() -> Result<u64> {
 
 // (low, middle, high)
 pub async fn fetch_bitcoin_gas_fee() -> Result<(u64, u64, u64)> {
-    let socket5 = conf::socket5();
     const API: &str = "https://blockstream.info/api/fee-estimates";
 
-    let client = if socket5.ethscan {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
-    } else {
-        Client::new()
-    };
+    let resp = upstream::send("blockstream", conf::socket5().blockstream, |c| c.get(API)).await?;
+    parse_bitcoin_gas_fee(upstream::json::<HashMap<String, f64>>(resp).await?)
+}
 
-    let mut response = client
-        .get(API)
-        .send()
-        .await?
-        .json::<HashMap<String, f64>>()
-        .await?
+fn parse_bitcoin_gas_fee(fees: HashMap<String, f64>) -> Result<(u64, u64, u64)> {
+    let mut response = fees
         .into_values()
+        .filter(|v| v.is_finite() && *v > 0.0)
         .map(|v| v as u64)
         .collect::<Vec<u64>>();
 
-    response.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    response.sort();
     match response.len() {
         0 => Err(anyhow!("no feerate provided")),
         1 => Ok((response[0], response[0], response[0])),
@@ -259,3 +624,160 @@ pub async fn fetch_bitcoin_gas_fee() -> Result<(u64, u64, u64)> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(symbol: &str, rank: u32, price: f64, change_24h: Option<f64>) -> Coin {
+        Coin {
+            symbol: symbol.to_string(),
+            name: symbol.to_lowercase(),
+            rank,
+            price,
+            percent_change_24h: change_24h,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let latest = Latest {
+            source: "coingecko".to_string(),
+            fetched_at: 1709251200,
+            coins: vec![Coin {
+                symbol: "BTC".to_string(),
+                name: "Bitcoin".to_string(),
+                rank: 1,
+                price: 61000.5,
+                last_updated: 1709251200,
+                ..Default::default()
+            }],
+        };
+
+        let v = listing(&latest);
+        assert_eq!(v["status"]["source"], "coingecko");
+        assert_eq!(v["status"]["timestamp"], "2024-03-01T00:00:00.000Z");
+        assert_eq!(v["data"][0]["cmc_rank"], 1);
+        assert_eq!(v["data"][0]["quote"]["USD"]["price"], 61000.5);
+        assert!(v["data"][0]["quote"]["USD"]["percent_change_7d"].is_null());
+    }
+
+    #[test]
+    fn test_select_coins() {
+        let coins = vec![
+            coin("BTC", 1, 61000.0, Some(2.0)),
+            coin("ETH", 2, 3400.0, None),
+            coin("SOL", 3, 130.0, Some(9.0)),
+        ];
+
+        let symbols = |v: Vec<Coin>| v.into_iter().map(|c| c.symbol).collect::<Vec<_>>();
+
+        assert_eq!(
+            symbols(select_coins(&coins, &[], CoinSort::Rank, false, 2)),
+            vec!["BTC", "ETH"]
+        );
+        assert_eq!(
+            symbols(select_coins(
+                &coins,
+                &[],
+                CoinSort::PercentChange24h,
+                true,
+                10
+            )),
+            vec!["SOL", "BTC", "ETH"]
+        );
+        assert_eq!(
+            symbols(select_coins(
+                &coins,
+                &["sol".to_string(), "btc".to_string()],
+                CoinSort::Price,
+                false,
+                10
+            )),
+            vec!["SOL", "BTC"]
+        );
+
+        let v = project_coins(&coins[..1], &["symbol", "price"]);
+        assert_eq!(v[0], json!({"symbol": "BTC", "price": 61000.0}));
+    }
+
+    #[test]
+    fn test_value_portfolio() {
+        let coins = vec![
+            coin("BTC", 1, 60000.0, None),
+            coin("ETH", 2, 3000.0, None),
+            coin("DEAD", 3, 0.0, None),
+        ];
+        let holding = |symbol: &str, amount, cost_basis| Holding {
+            symbol: symbol.to_string(),
+            amount,
+            cost_basis,
+        };
+        let holdings = vec![
+            holding("BTC", 0.5, Some(20000.0)),
+            holding("ETH", 10.0, None),
+            holding("DEAD", 1.0, Some(10.0)),
+            holding("NOPE", 1.0, None),
+        ];
+
+        let p = value_portfolio(&coins, &holdings, None);
+        assert_eq!(p.unpriced, ["DEAD", "NOPE"]);
+        assert_eq!(p.total_value, 60000.0);
+        assert_eq!((p.assets[0].value, p.assets[0].allocation), (30000.0, 50.0));
+        assert_eq!(p.assets[0].pnl, Some(10000.0));
+        assert_eq!(p.assets[0].pnl_percent, Some(50.0));
+        assert_eq!((p.assets[1].pnl, p.assets[1].allocation), (None, 50.0));
+
+        // Cost and PnL only count assets with a cost basis.
+        assert_eq!(
+            (p.total_cost, p.total_pnl, p.total_pnl_percent),
+            (Some(20000.0), Some(10000.0), Some(50.0))
+        );
+
+        let rate = Rate {
+            currency: "CNY".to_string(),
+            rate: 7.0,
+            source: "awtmt".to_string(),
+            fetched_at: 100,
+        };
+        let p = value_portfolio(&coins, &holdings[1..2], Some(&rate));
+        assert_eq!((p.assets[0].price, p.total_value), (21000.0, 210000.0));
+        assert_eq!((p.total_cost, p.total_pnl_percent), (None, None));
+
+        let p = value_portfolio(&coins, &[holding("ETH", 0.0, Some(0.0))], None);
+        assert_eq!((p.assets[0].allocation, p.total_pnl_percent), (0.0, None));
+    }
+
+    #[test]
+    fn test_validate_stats() {
+        let mut greed_fear: GreedFear =
+            serde_json::from_str(include_str!("../../../script/greed-fear.json")).unwrap();
+        assert!(greed_fear.validate().is_ok());
+        greed_fear.data[0].value = "101".to_string();
+        assert!(greed_fear.validate().is_err());
+        assert!(GreedFear::default().validate().is_err());
+
+        let mut global: Global =
+            serde_json::from_str(include_str!("../../../script/crypto-global.json")).unwrap();
+        assert!(global.validate().is_ok());
+        global.bitcoin_percentage_of_market_cap = f64::NAN;
+        assert!(global.validate().is_err());
+
+        let gas: Value =
+            serde_json::from_str(include_str!("../../../script/ethereum-gas-fee.json")).unwrap();
+        assert_eq!(parse_ethereum_gas_fee(&gas).unwrap(), 0x12745f666f);
+        assert!(
+            parse_ethereum_gas_fee(&json!({"status": "0", "result": "Invalid API Key"})).is_err()
+        );
+
+        let fees = HashMap::from([
+            ("1".to_string(), 20.5),
+            ("6".to_string(), 12.0),
+            ("144".to_string(), 3.1),
+            ("504".to_string(), 0.0),
+        ]);
+        assert_eq!(parse_bitcoin_gas_fee(fees).unwrap(), (3, 12, 20));
+        assert!(parse_bitcoin_gas_fee(HashMap::new()).is_err());
+    }
+}
@@ -1,21 +1,71 @@
+use crate::{db, middleware::request_id};
+use chrono::{DateTime, Utc};
 use rocket::{
-    http::{ContentType, Status},
+    http::{ContentType, Header, Method, Status},
     response::{Responder, Response, Result},
     Request,
 };
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 use std::io::Cursor;
 
 pub struct Data {
     data: Vec<u8>,
     r#type: ContentType,
+    headers: Vec<Header<'static>>,
+    error: Option<Error>,
+    cache: Option<Cache>,
     pub status: Status,
 }
 
+// A successful response clients may cache for `max_age` seconds and
+// revalidate with `If-None-Match` or `If-Modified-Since`.
+#[derive(Default)]
+struct Cache {
+    max_age: u64,
+    last_modified: Option<i64>,
+    etag: Option<String>,
+}
+
+// The body of every failure response. `request_id` is filled in when the
+// response is built so callers never have to thread the request through.
+#[derive(Serialize, Debug, Clone)]
+pub struct Error {
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: String,
+    pub details: Option<Value>,
+}
+
+pub fn error_code(status: Status) -> &'static str {
+    match status.code {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        405 => "method_not_allowed",
+        409 => "conflict",
+        413 => "payload_too_large",
+        415 => "unsupported_media_type",
+        422 => "unprocessable_entity",
+        429 => "too_many_requests",
+        502 => "upstream_error",
+        503 => "service_unavailable",
+        504 => "upstream_timeout",
+        c if (400..500).contains(&c) => "client_error",
+        _ => "internal_error",
+    }
+}
+
 impl Default for Data {
     fn default() -> Self {
         Data {
             data: vec![],
             r#type: ContentType::Plain,
+            headers: vec![],
+            error: None,
+            cache: None,
             status: Status::Ok,
         }
     }
@@ -23,28 +73,312 @@ impl Default for Data {
 
 impl Data {
     pub fn new(data: Vec<u8>, t: ContentType) -> Self {
+        Self::new_with_status(data, t, Status::Ok)
+    }
+
+    pub fn new_with_status(data: Vec<u8>, t: ContentType, status: Status) -> Self {
         Self {
             data,
             r#type: t,
-            status: Status::Ok,
+            status,
+            ..Default::default()
         }
     }
 
-    pub fn new_with_status(data: Vec<u8>, t: ContentType, status: Status) -> Self {
+    pub fn error(status: Status, message: impl Into<String>) -> Self {
         Self {
-            data,
-            r#type: t,
+            r#type: ContentType::JSON,
+            error: Some(Error {
+                code: error_code(status),
+                message: message.into(),
+                request_id: String::default(),
+                details: None,
+            }),
             status,
+            ..Default::default()
+        }
+    }
+
+    // Upstream failures are logged, the client only learns which source failed.
+    pub fn upstream_error(source: &str, e: &anyhow::Error) -> Self {
+        log::warn!("fetch {source} error: {e:?}");
+        Self::error(
+            Status::BadGateway,
+            format!("failed to fetch data from {source}"),
+        )
+        .with_details(serde_json::json!({ "source": source }))
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        if let Some(ref mut e) = self.error {
+            e.code = code;
         }
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        if let Some(ref mut e) = self.error {
+            e.details = Some(details);
+        }
+        self
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push(Header::new(name, value));
+        self
+    }
+
+    // Without it a response is sent with `Cache-Control: no-store`.
+    pub fn with_cache(mut self, max_age: u64, last_modified: Option<i64>) -> Self {
+        let cache = self.cache.get_or_insert_with(Cache::default);
+        cache.max_age = max_age;
+        cache.last_modified = last_modified;
+        self
+    }
+
+    // Tags the response by `content` instead of the body, for bodies with
+    // fields that change while the data does not.
+    pub fn with_etag(mut self, content: &[u8]) -> Self {
+        self.cache.get_or_insert_with(Cache::default).etag = Some(etag(content));
+        self
+    }
+}
+
+pub fn etag(content: &[u8]) -> String {
+    format!("\"{:x}\"", Sha256::digest(content))
+}
+
+pub fn http_date(t: DateTime<Utc>) -> String {
+    t.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// `If-None-Match` wins over `If-Modified-Since` when a request has both.
+fn is_not_modified(request: &Request<'_>, etag: &str, last_modified: Option<i64>) -> bool {
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return false;
+    }
+
+    let headers = request.headers();
+    if let Some(tags) = headers.get_one("If-None-Match") {
+        return tags
+            .split(',')
+            .map(str::trim)
+            .any(|t| t == "*" || t.strip_prefix("W/").unwrap_or(t) == etag);
+    }
+
+    match (headers.get_one("If-Modified-Since"), last_modified) {
+        (Some(since), Some(t)) => {
+            DateTime::parse_from_rfc2822(since).is_ok_and(|since| t <= since.timestamp())
+        }
+        _ => false,
+    }
+}
+
+// Only a generic message goes to the client, the database error is logged.
+impl From<db::Error> for Data {
+    fn from(e: db::Error) -> Self {
+        let (status, code, message) = match e {
+            db::Error::RowNotFound => (Status::NotFound, "not_found", "not found"),
+            db::Error::UniqueViolation => (Status::Conflict, "conflict", "already exists"),
+            db::Error::Busy => (
+                Status::ServiceUnavailable,
+                "database_busy",
+                "database is busy, try again later",
+            ),
+            db::Error::Unavailable => (
+                Status::ServiceUnavailable,
+                "database_unavailable",
+                "database is unavailable",
+            ),
+            db::Error::Other(ref e) => {
+                log::warn!("database error: {e:?}");
+                (
+                    Status::InternalServerError,
+                    "internal_error",
+                    "internal server error",
+                )
+            }
+        };
+
+        Data::error(status, message).with_code(code)
     }
 }
 
 impl<'a> Responder<'a, 'static> for Data {
-    fn respond_to(self, _: &'a Request<'_>) -> Result<'static> {
-        Response::build()
+    fn respond_to(mut self, request: &'a Request<'_>) -> Result<'static> {
+        if let Some(mut e) = self.error.take() {
+            e.request_id = request_id::get(request).to_string();
+            self.data = serde_json::to_vec(&e).unwrap_or_default();
+        }
+
+        let mut builder = Response::build();
+        for header in self.headers {
+            builder.header(header);
+        }
+
+        match self.cache {
+            Some(cache) if self.status == Status::Ok => {
+                let etag = cache.etag.unwrap_or_else(|| etag(&self.data));
+                builder
+                    .raw_header(
+                        "Cache-Control",
+                        format!("public, max-age={}", cache.max_age),
+                    )
+                    .raw_header("ETag", etag.clone());
+                if let Some(t) = cache
+                    .last_modified
+                    .and_then(|t| DateTime::from_timestamp(t, 0))
+                {
+                    builder.raw_header("Last-Modified", http_date(t));
+                }
+
+                if is_not_modified(request, &etag, cache.last_modified) {
+                    return builder.status(Status::NotModified).ok();
+                }
+            }
+            _ => {
+                builder.raw_header("Cache-Control", "no-store");
+            }
+        }
+
+        builder
             .header(self.r#type)
             .status(self.status)
             .sized_body(self.data.len(), Cursor::new(self.data))
             .ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::request_id::{self, RequestId};
+    use rocket::local::blocking::Client;
+
+    #[get("/conflict")]
+    fn conflict() -> Data {
+        Data::error(Status::Conflict, "already exists")
+            .with_details(serde_json::json!({ "uuid": "uuid-1" }))
+    }
+
+    // 2023-11-14 22:13:20 UTC
+    const FETCHED_AT: i64 = 1700000000;
+
+    #[get("/cached")]
+    fn cached() -> Data {
+        Data::new(b"{}".to_vec(), ContentType::JSON).with_cache(30, Some(FETCHED_AT))
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .attach(RequestId)
+            .mount("/", routes![conflict, cached]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_error_envelope() {
+        let client = client();
+        let resp = client
+            .get("/conflict")
+            .header(Header::new(request_id::HEADER, "req-1"))
+            .dispatch();
+
+        assert_eq!(resp.status(), Status::Conflict);
+        assert_eq!(resp.content_type(), Some(ContentType::JSON));
+        assert_eq!(resp.headers().get_one(request_id::HEADER), Some("req-1"));
+
+        let body: Value = serde_json::from_str(&resp.into_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "conflict");
+        assert_eq!(body["message"], "already exists");
+        assert_eq!(body["request_id"], "req-1");
+        assert_eq!(body["details"]["uuid"], "uuid-1");
+    }
+
+    #[test]
+    fn test_error_request_id_generated() {
+        let client = client();
+        let resp = client
+            .get("/conflict")
+            .header(Header::new(request_id::HEADER, "bad id with spaces"))
+            .dispatch();
+
+        let id = resp
+            .headers()
+            .get_one(request_id::HEADER)
+            .unwrap()
+            .to_string();
+        assert_ne!(id, "bad id with spaces");
+
+        let body: Value = serde_json::from_str(&resp.into_string().unwrap()).unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+    }
+
+    #[test]
+    fn test_db_error_status() {
+        assert_eq!(Data::from(db::Error::RowNotFound).status, Status::NotFound);
+        assert_eq!(
+            Data::from(db::Error::UniqueViolation).status,
+            Status::Conflict
+        );
+        assert_eq!(
+            Data::from(db::Error::Busy).status,
+            Status::ServiceUnavailable
+        );
+    }
+
+    #[test]
+    fn test_conditional_request() {
+        let client = client();
+        let resp = client.get("/cached").dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(
+            resp.headers().get_one("Cache-Control"),
+            Some("public, max-age=30")
+        );
+        assert_eq!(
+            resp.headers().get_one("Last-Modified"),
+            Some("Tue, 14 Nov 2023 22:13:20 GMT")
+        );
+        let tag = resp.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(tag, etag(b"{}"));
+
+        let get = |name: &'static str, value: String| {
+            client
+                .get("/cached")
+                .header(Header::new(name, value))
+                .dispatch()
+        };
+
+        let resp = get("If-None-Match", format!("\"other\", W/{tag}"));
+        assert_eq!(resp.status(), Status::NotModified);
+        assert_eq!(resp.headers().get_one("ETag"), Some(tag.as_str()));
+        assert!(resp.into_bytes().unwrap_or_default().is_empty());
+
+        assert_eq!(
+            get("If-None-Match", "\"other\"".to_string()).status(),
+            Status::Ok
+        );
+        assert_eq!(
+            get(
+                "If-Modified-Since",
+                "Tue, 14 Nov 2023 22:13:20 GMT".to_string()
+            )
+            .status(),
+            Status::NotModified
+        );
+        assert_eq!(
+            get(
+                "If-Modified-Since",
+                "Tue, 14 Nov 2023 22:13:19 GMT".to_string()
+            )
+            .status(),
+            Status::Ok
+        );
+
+        // Errors and responses without a cache policy are never stored.
+        let resp = client.get("/conflict").dispatch();
+        assert_eq!(resp.headers().get_one("Cache-Control"), Some("no-store"));
+        assert!(resp.headers().get_one("ETag").is_none());
+    }
+}
@@ -0,0 +1,235 @@
+// Exchange rates from the US dollar, which every upstream quotes in. Rates
+// come from awtmt quotes such as USDCNH and, when enabled, from open.er-api.com.
+use super::{cache, market::Quote, upstream};
+use crate::{
+    conf,
+    scheduler::{self, Job},
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+pub const BASE: &str = "USD";
+
+lazy_static! {
+    static ref RATES: Mutex<BTreeMap<String, Rate>> = Mutex::new(BTreeMap::new());
+}
+
+// Tests share the global rates, so every test writing them must hold this lock.
+#[cfg(test)]
+pub static TEST_MTX: rocket::tokio::sync::Mutex<()> = rocket::tokio::sync::Mutex::const_new(());
+
+// `rate` units of `currency` per US dollar.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub currency: String,
+    pub rate: f64,
+    pub source: String,
+    pub fetched_at: i64,
+}
+
+impl Rate {
+    pub fn convert(&self, usd: f64) -> f64 {
+        usd * self.rate
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ExchangeRateResponse {
+    result: String,
+    #[serde(default)]
+    time_last_update_unix: i64,
+    #[serde(default)]
+    rates: HashMap<String, f64>,
+}
+
+// Keeps the newer rate of a currency when two sources have one.
+pub fn update(rates: Vec<Rate>) {
+    let mut table = RATES.lock().unwrap();
+    for r in rates {
+        if table
+            .get(&r.currency)
+            .is_none_or(|old| old.fetched_at <= r.fetched_at)
+        {
+            table.insert(r.currency.clone(), r);
+        }
+    }
+}
+
+// The rate of `currency`, case insensitive. None for the dollar itself.
+pub fn rate(currency: &str) -> Option<Rate> {
+    RATES.lock().unwrap().get(&currency.to_uppercase()).cloned()
+}
+
+// Whether `currency` is expected to get a rate: a configured market pair, or
+// any currency code while the provider has not answered yet.
+pub fn is_pending(currency: &str) -> bool {
+    let conf = conf::fx();
+    let is_code = currency.len() == 3 && currency.bytes().all(|b| b.is_ascii_alphabetic());
+    conf.market.keys().any(|c| c.eq_ignore_ascii_case(currency))
+        || (conf.provider && is_code && cache::status("fx").is_none_or(|s| s.fetched_at.is_none()))
+}
+
+pub fn currencies() -> Vec<String> {
+    let mut v = vec![BASE.to_string()];
+    v.extend(RATES.lock().unwrap().keys().cloned());
+    v
+}
+
+// A quote without a usable last price, e.g. 0 before the session opens, is
+// skipped so the previous rate is kept.
+pub fn from_quotes(
+    pairs: &BTreeMap<String, String>,
+    quotes: &[Quote],
+    fetched_at: i64,
+) -> Vec<Rate> {
+    pairs
+        .iter()
+        .filter_map(|(currency, code)| {
+            let q = quotes
+                .iter()
+                .find(|q| &q.code == code)
+                .filter(|q| q.last.is_finite() && q.last > 0.0)?;
+            Some(Rate {
+                currency: currency.to_uppercase(),
+                rate: q.last,
+                source: "awtmt".to_string(),
+                fetched_at,
+            })
+        })
+        .collect()
+}
+
+pub async fn init() {
+    let conf = conf::fx();
+    if !conf.provider {
+        return;
+    }
+
+    if let Some(v) = cache::restore::<Vec<Rate>>("fx").await {
+        update(v);
+    }
+    scheduler::spawn(
+        Job::every("fx", conf.provider_interval_secs.max(60), refresh)
+            .with_timeout(upstream::job_timeout_secs(1)),
+    );
+}
+
+async fn refresh() -> Result<()> {
+    let v = cache::checked("fx", "exchangerate", fetch_exchangerate().await).await?;
+    update(v);
+    Ok(())
+}
+
+async fn fetch_exchangerate() -> Result<Vec<Rate>> {
+    const API: &str = "https://open.er-api.com/v6/latest/USD";
+
+    let resp = upstream::send("exchangerate", conf::socket5().exchangerate, |c| c.get(API)).await?;
+    parse_exchangerate(upstream::json(resp).await?)
+}
+
+fn parse_exchangerate(resp: ExchangeRateResponse) -> Result<Vec<Rate>> {
+    if resp.result != "success" || resp.time_last_update_unix <= 0 {
+        bail!("unexpected result `{}`", resp.result);
+    }
+
+    if let Some((currency, rate)) = resp
+        .rates
+        .iter()
+        .find(|(_, r)| !r.is_finite() || **r <= 0.0)
+    {
+        bail!("invalid rate {rate} of `{currency}`");
+    }
+
+    let rates = resp
+        .rates
+        .into_iter()
+        .filter(|(currency, _)| currency != BASE)
+        .map(|(currency, rate)| Rate {
+            currency,
+            rate,
+            source: "exchangerate".to_string(),
+            fetched_at: resp.time_last_update_unix,
+        })
+        .collect::<Vec<_>>();
+
+    if rates.is_empty() {
+        bail!("no rates");
+    }
+    Ok(rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(code: &str, last: f64) -> Quote {
+        Quote {
+            code: code.to_string(),
+            name: code.to_string(),
+            last,
+            prev_close: last,
+            change: 0.0,
+            change_rate: 0.0,
+            precision: 4,
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let _mtx = TEST_MTX.blocking_lock();
+        let pairs = BTreeMap::from([
+            ("TEST1".to_string(), "USDTEST1.OTC".to_string()),
+            ("TEST2".to_string(), "USDTEST2.OTC".to_string()),
+        ]);
+        update(from_quotes(&pairs, &[quote("USDTEST1.OTC", 7.2)], 100));
+
+        let r = rate("test1").unwrap();
+        assert_eq!(
+            (r.rate, r.source.as_str(), r.fetched_at),
+            (7.2, "awtmt", 100)
+        );
+        assert_eq!(r.convert(10.0), 72.0);
+        assert!(rate("TEST2").is_none());
+
+        for last in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(from_quotes(&pairs, &[quote("USDTEST2.OTC", last)], 100).is_empty());
+        }
+
+        // An older rate from another source does not win.
+        let older = |rate, fetched_at| Rate {
+            currency: "TEST1".to_string(),
+            rate,
+            source: "exchangerate".to_string(),
+            fetched_at,
+        };
+        update(vec![older(7.0, 50)]);
+        assert_eq!(rate("TEST1").unwrap().rate, 7.2);
+        update(vec![older(7.3, 200)]);
+        assert_eq!(rate("TEST1").unwrap().source, "exchangerate");
+        assert!(currencies().starts_with(&["USD".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_exchangerate() {
+        let resp = |v: &str| serde_json::from_str::<ExchangeRateResponse>(v).unwrap();
+
+        let rates = parse_exchangerate(resp(
+            r#"{"result": "success", "time_last_update_unix": 1700000000,
+                "rates": {"USD": 1, "CNY": 7.21}}"#,
+        ))
+        .unwrap();
+        assert_eq!(rates.len(), 1);
+        assert_eq!((rates[0].currency.as_str(), rates[0].rate), ("CNY", 7.21));
+        assert_eq!(rates[0].fetched_at, 1700000000);
+
+        assert!(parse_exchangerate(resp(r#"{"result": "error"}"#)).is_err());
+        assert!(parse_exchangerate(resp(
+            r#"{"result": "success", "time_last_update_unix": 1, "rates": {"CNY": 0}}"#
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use super::{cryptocurrency::latest_interval, provider::Latest};
+use crate::{
+    conf,
+    config::HistoryTier,
+    db::{self, history::Candle},
+};
+use chrono::Utc;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct History {
+    pub symbol: String,
+    pub interval: i64,
+
+    // The stored resolution the candles were built from.
+    pub resolution: i64,
+    pub from: i64,
+    pub to: i64,
+    pub data: Vec<Candle>,
+}
+
+// Samples only arrive with the `latest` refresh, a tier finer than it would
+// hold one point per refresh at best and is left out.
+pub fn fillable(tiers: Vec<HistoryTier>, refresh: u64) -> Vec<HistoryTier> {
+    tiers
+        .into_iter()
+        .filter(|t| t.resolution >= refresh as i64)
+        .collect()
+}
+
+pub fn tiers() -> Vec<HistoryTier> {
+    fillable(conf::history().tiers, latest_interval())
+}
+
+// Folds a refresh into every tier and drops what fell out of retention.
+pub async fn record(latest: &Latest) -> db::Result<()> {
+    let tiers = tiers();
+    let samples = latest
+        .coins
+        .iter()
+        .map(|c| (c.symbol.as_str(), c.price))
+        .collect::<Vec<_>>();
+    let resolutions = tiers.iter().map(|t| t.resolution).collect::<Vec<_>>();
+
+    db::history::record(&samples, latest.fetched_at, &resolutions).await?;
+
+    let now = Utc::now().timestamp();
+    for t in tiers {
+        db::history::prune(t.resolution, now - t.retention).await?;
+    }
+    Ok(())
+}
+
+// The finest tier that `interval` is a multiple of and that still covers
+// `from`, or the longest kept one when none reaches back that far.
+pub fn choose_tier(
+    tiers: &[HistoryTier],
+    interval: i64,
+    from: i64,
+    now: i64,
+) -> Option<HistoryTier> {
+    let mut candidates = tiers
+        .iter()
+        .filter(|t| t.resolution > 0 && interval % t.resolution == 0)
+        .copied()
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|t| t.resolution);
+
+    candidates
+        .iter()
+        .find(|t| now - t.retention <= from)
+        .or_else(|| candidates.iter().max_by_key(|t| t.retention))
+        .copied()
+}
+
+// Merges candles sorted by time into buckets of `interval` seconds.
+pub fn aggregate(candles: Vec<Candle>, interval: i64) -> Vec<Candle> {
+    let mut merged: Vec<Candle> = vec![];
+
+    for c in candles {
+        let time = c.time - c.time.rem_euclid(interval);
+        match merged.last_mut() {
+            Some(last) if last.time == time => {
+                last.high = last.high.max(c.high);
+                last.low = last.low.min(c.low);
+                last.close = c.close;
+            }
+            _ => merged.push(Candle { time, ..c }),
+        }
+    }
+
+    merged
+}
+
+// `from` comes aligned to `interval` from `HistoryQuery::range`.
+pub async fn candles(
+    symbol: &str,
+    interval: i64,
+    from: i64,
+    to: i64,
+) -> db::Result<Option<History>> {
+    let now = Utc::now().timestamp();
+    let Some(tier) = choose_tier(&tiers(), interval, from, now) else {
+        return Ok(None);
+    };
+
+    let symbol = symbol.to_uppercase();
+    let data = db::history::select(&symbol, tier.resolution, from, to).await?;
+
+    Ok(Some(History {
+        symbol,
+        interval,
+        resolution: tier.resolution,
+        from,
+        to,
+        data: aggregate(data, interval),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: HistoryTier = HistoryTier {
+        resolution: 60,
+        retention: 2 * 86400,
+    };
+    const HOUR: HistoryTier = HistoryTier {
+        resolution: 3600,
+        retention: 365 * 86400,
+    };
+
+    #[test]
+    fn test_choose_tier() {
+        let (tiers, now) = ([MINUTE, HOUR], 400 * 86400);
+
+        assert_eq!(choose_tier(&tiers, 300, now - 3600, now), Some(MINUTE));
+        assert_eq!(choose_tier(&tiers, 3600, now - 3600, now), Some(MINUTE));
+        assert_eq!(choose_tier(&tiers, 3600, now - 7 * 86400, now), Some(HOUR));
+        assert_eq!(choose_tier(&tiers, 300, now - 7 * 86400, now), Some(MINUTE));
+        assert_eq!(choose_tier(&tiers, 90, now, now), None);
+    }
+
+    #[test]
+    fn test_fillable() {
+        assert_eq!(fillable(vec![MINUTE, HOUR], 60), vec![MINUTE, HOUR]);
+        assert_eq!(fillable(vec![MINUTE, HOUR], 1800), vec![HOUR]);
+        assert_eq!(fillable(vec![MINUTE, HOUR], 7200), vec![]);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let c = |time, open, high, low, close| Candle {
+            time,
+            open,
+            high,
+            low,
+            close,
+        };
+
+        let merged = aggregate(
+            vec![
+                c(0, 10.0, 12.0, 9.0, 11.0),
+                c(60, 11.0, 15.0, 11.0, 14.0),
+                c(300, 14.0, 14.0, 8.0, 8.0),
+            ],
+            300,
+        );
+        assert_eq!(
+            merged,
+            vec![c(0, 10.0, 15.0, 9.0, 14.0), c(300, 14.0, 14.0, 8.0, 8.0)]
+        );
+    }
+}
@@ -1,15 +1,41 @@
-use super::RUNTIME;
-use crate::conf;
-use anyhow::Result;
-use reqwest::{Client, Proxy};
-use rocket::tokio::{self, sync::Mutex, time::Duration};
+use super::{
+    cache::{self, Freshness},
+    fx, market_history,
+    session::{self, Session},
+    stream::{self, Topic},
+    upstream,
+};
+use crate::{
+    conf,
+    scheduler::{self, Job},
+};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use rocket::tokio::sync::Mutex;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::HashMap;
+use serde_json::{self, Value};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const API: &str = "https://api-ddc-wscn.awtmt.com/market/real";
+
+// The columns asked for, rows are read by the `fields` of the reply.
+const FIELDS: [&str; 6] = [
+    "prod_name",
+    "preclose_px",
+    "last_px",
+    "px_change",
+    "px_change_rate",
+    "price_precision",
+];
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SnapshotData {
-    snapshot: HashMap<String, Vec<serde_json::Value>>,
+    #[serde(default)]
+    fields: Vec<String>,
+    snapshot: HashMap<String, Vec<Value>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,74 +45,314 @@ struct ResponseData {
     data: SnapshotData,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub code: String,
+    pub name: String,
+    pub last: f64,
+    pub prev_close: f64,
+    pub change: f64,
+    pub change_rate: f64,
+
+    // Decimal places the price is quoted with.
+    pub precision: u32,
+}
+
+// The layout of `/market/latest` the apps were built against.
 #[derive(Serialize, Deserialize, Debug)]
-struct MarketData {
-    name: String,
-    value: f64,
-    precent: f64,
+pub struct MarketData {
+    pub name: String,
+    pub value: f64,
+    pub precent: f64,
+}
+
+// A quote with whether its exchange is trading right now.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SessionQuote<T> {
+    #[serde(flatten)]
+    pub quote: T,
+    pub session: Session,
 }
 
 lazy_static! {
-    static ref MARKET_DATA: Mutex<Option<String>> = Mutex::new(None);
+    static ref QUOTES: Mutex<Option<Vec<Quote>>> = Mutex::new(None);
 }
 
-pub async fn latest_cache() -> Option<String> {
-    MARKET_DATA.lock().await.clone()
+// Whether the last successful refresh happened during a session, the first
+// refresh after the close picks up the closing values.
+static WAS_LIVE: AtomicBool = AtomicBool::new(true);
+
+pub async fn quotes_cache() -> Option<Vec<Quote>> {
+    QUOTES.lock().await.clone()
+}
+
+impl From<&Quote> for MarketData {
+    fn from(q: &Quote) -> Self {
+        Self {
+            name: q.name.clone(),
+            value: q.last,
+            precent: q.change_rate,
+        }
+    }
 }
 
-pub fn init() {
-    timer();
+// Sorted by code as before the typed model.
+pub fn by_code(quotes: &[Quote]) -> Vec<&Quote> {
+    let mut quotes = quotes.iter().collect::<Vec<_>>();
+    quotes.sort_by(|a, b| a.code.cmp(&b.code));
+    quotes
 }
 
-fn timer() {
-    RUNTIME.lock().unwrap().spawn(async move {
-        log::debug!("latest timer start...");
+pub fn legacy(quotes: &[Quote]) -> Vec<MarketData> {
+    by_code(quotes).into_iter().map(MarketData::from).collect()
+}
 
-        let mut count = 0_u64;
-        let interval = u64::max(10, conf::timer().awtmt_market);
-        loop {
-            if count % interval == 0 {
-                match fetch().await {
-                    Ok(v) => *MARKET_DATA.lock().await = Some(v),
-                    Err(e) => log::warn!("fetch awtmt market data error: {e:?}"),
-                }
-            }
+pub fn with_session<T>(code: &str, quote: T) -> SessionQuote<T> {
+    SessionQuote {
+        quote,
+        session: session::state(&conf::market(), code, Utc::now()),
+    }
+}
+
+// Closing values only go stale when the next poll while closed is overdue.
+pub fn freshness() -> Freshness {
+    let conf = conf::market();
+    let threshold = conf::freshness().threshold("market");
+    if session::any_live(&conf, Utc::now()) {
+        return cache::freshness_within("market", threshold);
+    }
+
+    match conf.closed_interval_secs {
+        0 => cache::freshness_within("market", i64::MAX),
+        secs => cache::freshness_within("market", threshold + secs as i64),
+    }
+}
 
-            tokio::time::sleep(Duration::from_secs(1)).await;
-            count += 1;
+pub async fn init() {
+    if let Some(v) = cache::restore::<Vec<Quote>>("market").await {
+        if let Some(fetched_at) = cache::status("market").and_then(|s| s.fetched_at) {
+            fx::update(fx::from_quotes(&conf::fx().market, &v, fetched_at));
         }
-    });
+        publish(&v);
+        *QUOTES.lock().await = Some(v);
+    }
+
+    let interval = u64::max(10, conf::timer().awtmt_market);
+    scheduler::spawn(
+        Job::every("awtmt", interval, refresh).with_timeout(upstream::job_timeout_secs(1)),
+    );
+}
+
+// Outside every session the market is polled once for the closing values,
+// then every `closed_interval_secs` if that is not 0.
+fn should_poll(
+    live: bool,
+    was_live: bool,
+    status: Option<&cache::Status>,
+    closed_interval_secs: u64,
+    now: i64,
+) -> bool {
+    if live || was_live {
+        return true;
+    }
+
+    match status.and_then(|s| Some((s.fetched_at?, s.stale))) {
+        None | Some((_, true)) => true,
+        Some(_) if closed_interval_secs == 0 => false,
+        Some((fetched_at, _)) => now - fetched_at >= closed_interval_secs as i64,
+    }
+}
+
+async fn refresh() -> Result<()> {
+    let conf = conf::market();
+    let now = Utc::now();
+    let live = session::any_live(&conf, now);
+    if !should_poll(
+        live,
+        WAS_LIVE.load(Ordering::Relaxed),
+        cache::status("market").as_ref(),
+        conf.closed_interval_secs,
+        now.timestamp(),
+    ) {
+        return Ok(());
+    }
+
+    let v = cache::checked("market", "awtmt", fetch().await).await?;
+    WAS_LIVE.store(live, Ordering::Relaxed);
+    fx::update(fx::from_quotes(&conf::fx().market, &v, now.timestamp()));
+    if let Err(e) = market_history::record(&v, now).await {
+        log::warn!("record market history error: {e:?}");
+    }
+    publish(&v);
+    *QUOTES.lock().await = Some(v);
+    Ok(())
+}
+
+fn publish(quotes: &[Quote]) {
+    match serde_json::to_string(&legacy(quotes)) {
+        Ok(v) => stream::publish(Topic::Market, v),
+        Err(e) => log::warn!("serialize market error: {e:?}"),
+    }
 }
 
-pub async fn fetch() -> Result<String> {
-    fetch_awtmt().await
+pub async fn fetch() -> Result<Vec<Quote>> {
+    fetch_awtmt(&conf::market().products).await
 }
 
-async fn fetch_awtmt() -> Result<String> {
-    const API: &str = "https://api-ddc-wscn.awtmt.com/market/real?fields=prod_name%2Cpreclose_px%2Clast_px%2Cpx_change%2Cpx_change_rate%2Cprice_precision&prod_code=000001.SS%2CDXY.OTC%2CUS10YR.OTC%2CUSDCNH.OTC%2C399001.SZ%2C399006.SZ%2CUS500.OTC";
+async fn fetch_awtmt(products: &[String]) -> Result<Vec<Quote>> {
+    if products.is_empty() {
+        bail!("no market products configured");
+    }
 
-    let socket5 = conf::socket5();
-    let client = if socket5.awtmt {
-        let proxy = Proxy::all(format!("socks5://{}:{}", socket5.ip, socket5.port))?;
-        Client::builder().proxy(proxy).build()?
-    } else {
-        Client::new()
+    let query = [
+        ("fields", FIELDS.join(",")),
+        ("prod_code", products.join(",")),
+    ];
+    let resp = upstream::send("awtmt", conf::socket5().awtmt, |c| c.get(API).query(&query)).await?;
+
+    parse(upstream::json(resp).await?, products)
+}
+
+fn quote(code: &str, row: &[Value], fields: &[String]) -> Option<Quote> {
+    let value = |name: &str| row.get(fields.iter().position(|f| f == name)?);
+    let number = |name: &str| value(name)?.as_f64().filter(|v| v.is_finite());
+
+    let precision = match value("price_precision")? {
+        Value::String(s) => s.parse().ok()?,
+        v => v.as_u64()? as u32,
     };
 
-    let resp = client.get(API).send().await?.json::<ResponseData>().await?;
+    Some(Quote {
+        code: code.to_string(),
+        name: value("prod_name")?.as_str()?.to_string(),
+        last: number("last_px")?,
+        prev_close: number("preclose_px")?,
+        change: number("px_change")?,
+        change_rate: number("px_change_rate")?,
+        precision,
+    })
+}
+
+// The quotes of `products` in their order. Rejects error replies and
+// snapshots with a row that is not a usable quote.
+fn parse(resp: ResponseData, products: &[String]) -> Result<Vec<Quote>> {
+    if resp.code != 20000 {
+        bail!("error {}: {}", resp.code, resp.message);
+    }
+
+    let fields = match resp.data.fields {
+        v if v.is_empty() => FIELDS.map(String::from).to_vec(),
+        v => v,
+    };
+
+    let mut quotes = vec![];
+    for code in products {
+        let Some(row) = resp.data.snapshot.get(code) else {
+            continue;
+        };
+
+        match quote(code, row, &fields) {
+            Some(q) if !q.name.is_empty() && q.last > 0.0 => quotes.push(q),
+            _ => bail!("invalid quote of `{code}`"),
+        }
+    }
+
+    if quotes.is_empty() {
+        bail!("empty snapshot");
+    }
+
+    Ok(quotes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKET: &str = include_str!("../../../script/market.json");
+
+    fn products() -> Vec<String> {
+        conf::market().products
+    }
+
+    #[test]
+    fn test_parse() {
+        let quotes = parse(serde_json::from_str(MARKET).unwrap(), &products()).unwrap();
+        assert_eq!(quotes.len(), 7);
+        assert_eq!(
+            quotes[0],
+            Quote {
+                code: "000001.SS".to_string(),
+                name: "上证指数".to_string(),
+                last: 3027.0204,
+                prev_close: 3015.1712,
+                change: 11.849199999999655,
+                change_rate: 0.3929859770483233,
+                precision: 2,
+            }
+        );
+        assert_eq!(quotes[3].code, "USDCNH.OTC");
+        assert_eq!(quotes[3].precision, 4);
+
+        // The old layout keeps its order by code.
+        let legacy = legacy(&quotes);
+        assert_eq!(legacy[0].name, "上证指数");
+        assert_eq!(legacy[0].value, 3027.0204);
+        assert_eq!(legacy[1].name, "深证成指");
+
+        let subset = ["US500.OTC".to_string(), "FOO.OTC".to_string()];
+        let quotes = parse(serde_json::from_str(MARKET).unwrap(), &subset).unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!((quotes[0].last, quotes[0].change), (5137.08, 40.81));
+
+        let mut resp: ResponseData = serde_json::from_str(MARKET).unwrap();
+        resp.code = 40000;
+        assert!(parse(resp, &products()).is_err());
+
+        let mut resp: ResponseData = serde_json::from_str(MARKET).unwrap();
+        resp.data
+            .snapshot
+            .values_mut()
+            .for_each(|v| v[2] = Value::Null);
+        assert!(parse(resp, &products()).is_err());
+
+        let mut resp: ResponseData = serde_json::from_str(MARKET).unwrap();
+        resp.data.snapshot.clear();
+        assert!(parse(resp, &products()).is_err());
+    }
+
+    #[test]
+    fn test_should_poll() {
+        let status = cache::Status {
+            fetched_at: Some(1000),
+            ..Default::default()
+        };
+
+        assert!(should_poll(true, false, Some(&status), 0, 1001));
+        assert!(should_poll(false, true, Some(&status), 0, 1001));
+        assert!(!should_poll(false, false, Some(&status), 0, 100000));
+        assert!(!should_poll(false, false, Some(&status), 1800, 2799));
+        assert!(should_poll(false, false, Some(&status), 1800, 2800));
 
-    let mut resp = resp.data.snapshot.into_iter().collect::<Vec<(_, _)>>();
-    resp.sort_by(|a, b| a.0.cmp(&b.0));
+        // Nothing or only a restored snapshot to serve.
+        assert!(should_poll(false, false, None, 0, 1001));
+        let restored = cache::Status {
+            stale: true,
+            ..status
+        };
+        assert!(should_poll(false, false, Some(&restored), 0, 1001));
+    }
 
-    let resp = resp
-        .into_iter()
-        .filter(|(_, item)| item.len() == 7)
-        .map(|(_, item)| MarketData {
-            name: item[0].as_str().unwrap_or_default().to_string(),
-            value: item[2].as_f64().unwrap_or_default(),
-            precent: item[4].as_f64().unwrap_or_default(),
-        })
-        .collect::<Vec<_>>();
+    #[test]
+    fn test_parse_by_fields() {
+        // Columns are found by name, not by position.
+        let mut resp: ResponseData = serde_json::from_str(MARKET).unwrap();
+        resp.data.fields.swap(1, 2);
+        resp.data.snapshot.values_mut().for_each(|v| v.swap(1, 2));
 
-    Ok(serde_json::to_string(&resp)?)
+        let quotes = parse(resp, &products()).unwrap();
+        assert_eq!(
+            (quotes[0].last, quotes[0].prev_close),
+            (3027.0204, 3015.1712)
+        );
+    }
 }
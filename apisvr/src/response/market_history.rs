@@ -0,0 +1,140 @@
+use super::{
+    history::aggregate,
+    market::Quote,
+    session::{self, Session},
+};
+use crate::{
+    conf,
+    db::{self, history::Candle},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+const DAY: i64 = 86400;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Intraday,
+    Daily,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct History {
+    pub code: String,
+    pub interval: i64,
+
+    // Whole days are built from the daily closes, anything shorter from the
+    // intraday samples.
+    pub source: Source,
+    pub from: i64,
+    pub to: i64,
+    pub data: Vec<Candle>,
+}
+
+// Live quotes become intraday samples and every quote of a trading day that
+// has opened updates its daily close.
+pub async fn record(quotes: &[Quote], now: DateTime<Utc>) -> db::Result<()> {
+    let conf = conf::market();
+    let ts = now.timestamp();
+
+    let samples = quotes
+        .iter()
+        .filter(|q| session::state(&conf, &q.code, now) == Session::Live)
+        .map(|q| (q.code.as_str(), q.last))
+        .collect::<Vec<_>>();
+    db::market::record_samples(&samples, ts).await?;
+
+    let closes = quotes
+        .iter()
+        .filter_map(|q| {
+            let date = session::trading_date(&conf, &q.code, now)?;
+            let day = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+            Some((q.code.as_str(), day, q.last))
+        })
+        .collect::<Vec<_>>();
+    db::market::record_closes(&closes).await?;
+
+    db::market::prune_samples(ts - conf.intraday_retention).await?;
+    Ok(())
+}
+
+pub fn source(interval: i64) -> Source {
+    if interval % DAY == 0 {
+        Source::Daily
+    } else {
+        Source::Intraday
+    }
+}
+
+// `from` comes aligned to `interval` from `HistoryQuery::range`.
+pub async fn candles(code: &str, interval: i64, from: i64, to: i64) -> db::Result<History> {
+    let source = source(interval);
+    let data = match source {
+        Source::Daily => db::market::select_closes(code, from, to).await?,
+        Source::Intraday => db::market::select_samples(code, from, to).await?,
+    };
+
+    Ok(History {
+        code: code.to_string(),
+        interval,
+        source,
+        from,
+        to,
+        data: aggregate(data, interval),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio;
+
+    fn quote(code: &str, last: f64) -> Quote {
+        Quote {
+            code: code.to_string(),
+            name: code.to_string(),
+            last,
+            prev_close: last,
+            change: 0.0,
+            change_rate: 0.0,
+            precision: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_candles() {
+        let _mtx = db::TEST_MTX.lock().await;
+        let _ = std::fs::remove_file("/tmp/market-candles-test.db");
+        db::init("/tmp/market-candles-test.db").await;
+
+        // Monday 2024-03-04 10:00 and 10:05 in Shanghai, the US is closed.
+        let t = |v| DateTime::parse_from_rfc3339(v).unwrap().to_utc();
+        for (at, sh, us) in [
+            ("2024-03-04T02:00:00Z", 3000.0, 5100.0),
+            ("2024-03-04T02:05:00Z", 3010.0, 5100.0),
+        ] {
+            record(&[quote("000001.SS", sh), quote("US500.OTC", us)], t(at))
+                .await
+                .unwrap();
+        }
+
+        let day = t("2024-03-04T00:00:00Z").timestamp();
+        let h = candles("000001.SS", 3600, day, day + DAY).await.unwrap();
+        assert_eq!(h.source, Source::Intraday);
+        assert_eq!(h.data.len(), 1);
+        assert_eq!((h.data[0].open, h.data[0].close), (3000.0, 3010.0));
+
+        let h = candles("000001.SS", DAY, day, day + DAY).await.unwrap();
+        assert_eq!(h.source, Source::Daily);
+        assert_eq!((h.data[0].time, h.data[0].close), (day, 3010.0));
+
+        // It is Sunday evening in New York, neither a sample nor a close.
+        let us = candles("US500.OTC", 60, day, day + DAY).await.unwrap();
+        assert!(us.data.is_empty());
+        let us = candles("US500.OTC", DAY, day - 3 * DAY, day + DAY)
+            .await
+            .unwrap();
+        assert!(us.data.is_empty());
+    }
+}
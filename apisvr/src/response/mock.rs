@@ -0,0 +1,77 @@
+// A minimal HTTP server for tests that answers each request path with a
+// canned status and body. Query strings are ignored when matching.
+use rocket::tokio::{
+    self,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Default)]
+pub struct Server {
+    pub base_url: String,
+    routes: Arc<Mutex<HashMap<String, (u16, String)>>>,
+    hits: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Server {
+    pub async fn start() -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Server {
+            base_url: format!("http://{}", listener.local_addr().unwrap()),
+            ..Default::default()
+        };
+
+        let s = server.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0_u8; 64 * 1024];
+                    let n = stream.read(&mut buf).await.unwrap_or_default();
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let path = request
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or("/")
+                        .split('?')
+                        .next()
+                        .unwrap_or("/")
+                        .to_string();
+
+                    *s.hits.lock().unwrap().entry(path.clone()).or_default() += 1;
+                    let (status, body) = s
+                        .routes
+                        .lock()
+                        .unwrap()
+                        .get(&path)
+                        .cloned()
+                        .unwrap_or((404, "not found".to_string()));
+
+                    let resp = format!(
+                        "HTTP/1.1 {status} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(resp.as_bytes()).await;
+                });
+            }
+        });
+
+        server
+    }
+
+    pub fn route(&self, path: &str, status: u16, body: impl Into<String>) -> &Self {
+        self.routes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (status, body.into()));
+        self
+    }
+
+    pub fn hits(&self, path: &str) -> usize {
+        self.hits.lock().unwrap().get(path).copied().unwrap_or(0)
+    }
+}
@@ -1,6 +1,18 @@
+pub mod alert;
+pub mod cache;
 pub mod cryptocurrency;
 pub mod data;
+pub mod fx;
+pub mod history;
 pub mod market;
+pub mod market_history;
+pub mod provider;
+pub mod session;
+pub mod stream;
+pub mod upstream;
+
+#[cfg(test)]
+pub mod mock;
 
 use rocket::tokio;
 use std::sync::Mutex;
@@ -10,7 +22,9 @@ lazy_static! {
         Mutex::new(tokio::runtime::Runtime::new().unwrap());
 }
 
-pub fn init() {
-    cryptocurrency::init();
-    market::init();
+pub async fn init() {
+    alert::init().await;
+    cryptocurrency::init().await;
+    market::init().await;
+    fx::init().await;
 }
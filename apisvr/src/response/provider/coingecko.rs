@@ -0,0 +1,114 @@
+use super::{parse_timestamp, Coin, PriceProvider};
+use crate::{conf, response::upstream};
+use anyhow::Result;
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+
+pub const NAME: &str = "coingecko";
+pub const MARKETS_PATH: &str = "/api/v3/coins/markets";
+const BASE_URL: &str = "https://api.coingecko.com";
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    symbol: String,
+    name: String,
+    current_price: Option<f64>,
+    market_cap: Option<f64>,
+    market_cap_rank: Option<u32>,
+    total_volume: Option<f64>,
+    circulating_supply: Option<f64>,
+    total_supply: Option<f64>,
+    max_supply: Option<f64>,
+    price_change_percentage_1h_in_currency: Option<f64>,
+    price_change_percentage_24h_in_currency: Option<f64>,
+    price_change_percentage_7d_in_currency: Option<f64>,
+    last_updated: Option<String>,
+}
+
+pub struct CoinGecko {
+    base_url: String,
+    api_key: String,
+    use_proxy: bool,
+
+    // The circuit breaker the requests go through.
+    breaker: String,
+}
+
+impl Default for CoinGecko {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            api_key: conf::api_key().coingecko,
+            use_proxy: conf::socket5().coingecko,
+            breaker: NAME.to_string(),
+        }
+    }
+}
+
+impl CoinGecko {
+    // A breaker of its own per `base_url`, so mock servers do not share the
+    // state of the real endpoint or of each other.
+    #[cfg(test)]
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            api_key: api_key.to_string(),
+            use_proxy: false,
+            breaker: format!("{NAME} {base_url}"),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn latest(&self, limit: usize) -> Result<Vec<Coin>> {
+        let items = upstream::send(&self.breaker, self.use_proxy, |c| {
+            let req = c
+                .get(format!("{}{}", self.base_url, MARKETS_PATH))
+                .header(ACCEPT, "application/json")
+                .query(&[
+                    ("vs_currency", "usd"),
+                    ("order", "market_cap_desc"),
+                    ("per_page", &limit.to_string()),
+                    ("page", "1"),
+                    ("price_change_percentage", "1h,24h,7d"),
+                ]);
+
+            if self.api_key.is_empty() {
+                req
+            } else {
+                req.header("x-cg-demo-api-key", &self.api_key)
+            }
+        })
+        .await?
+        .error_for_status()?
+        .json::<Vec<Item>>()
+        .await?;
+
+        Ok(items
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                Some(Coin {
+                    rank: item.market_cap_rank.unwrap_or(index as u32 + 1),
+                    price: item.current_price?,
+                    market_cap: item.market_cap.unwrap_or_default(),
+                    volume_24h: item.total_volume.unwrap_or_default(),
+                    percent_change_1h: item.price_change_percentage_1h_in_currency,
+                    percent_change_24h: item.price_change_percentage_24h_in_currency,
+                    percent_change_7d: item.price_change_percentage_7d_in_currency,
+                    circulating_supply: item.circulating_supply,
+                    total_supply: item.total_supply,
+                    max_supply: item.max_supply,
+                    last_updated: parse_timestamp(item.last_updated.as_deref()),
+                    symbol: item.symbol.to_uppercase(),
+                    name: item.name,
+                })
+            })
+            .collect())
+    }
+}
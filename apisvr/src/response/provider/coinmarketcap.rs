@@ -0,0 +1,143 @@
+use super::{parse_timestamp, Coin, PriceProvider};
+use crate::conf;
+use crate::response::upstream;
+use anyhow::{bail, Result};
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const NAME: &str = "coinmarketcap";
+pub const LISTINGS_PATH: &str = "/v1/cryptocurrency/listings/latest";
+const BASE_URL: &str = "https://pro-api.coinmarketcap.com";
+
+#[derive(Deserialize, Debug)]
+struct Listing {
+    status: Status,
+
+    #[serde(default)]
+    data: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Status {
+    error_code: i64,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    name: String,
+    symbol: String,
+    cmc_rank: Option<u32>,
+    circulating_supply: Option<f64>,
+    total_supply: Option<f64>,
+    max_supply: Option<f64>,
+    last_updated: Option<String>,
+    quote: HashMap<String, Quote>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Quote {
+    price: Option<f64>,
+    volume_24h: Option<f64>,
+    market_cap: Option<f64>,
+    percent_change_1h: Option<f64>,
+    percent_change_24h: Option<f64>,
+    percent_change_7d: Option<f64>,
+}
+
+pub struct CoinMarketCap {
+    base_url: String,
+    api_key: String,
+    use_proxy: bool,
+
+    // The circuit breaker the requests go through.
+    breaker: String,
+}
+
+impl Default for CoinMarketCap {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            api_key: conf::api_key().coinmarketcap,
+            use_proxy: conf::socket5().coinmarketcap,
+            breaker: NAME.to_string(),
+        }
+    }
+}
+
+impl CoinMarketCap {
+    // A breaker of its own per `base_url`, so mock servers do not share the
+    // state of the real endpoint or of each other.
+    #[cfg(test)]
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            api_key: api_key.to_string(),
+            use_proxy: false,
+            breaker: format!("{NAME} {base_url}"),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl PriceProvider for CoinMarketCap {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn latest(&self, limit: usize) -> Result<Vec<Coin>> {
+        let resp = upstream::send(&self.breaker, self.use_proxy, |c| {
+            c.get(format!("{}{}", self.base_url, LISTINGS_PATH))
+                .header(ACCEPT, "application/json")
+                .header("X-CMC_PRO_API_KEY", &self.api_key)
+                .query(&[
+                    ("start", "1"),
+                    ("limit", &limit.to_string()),
+                    ("convert", "USD"),
+                    ("aux", "cmc_rank"),
+                ])
+        })
+        .await?;
+
+        // CoinMarketCap explains most failures in `status`, also on 4xx.
+        let http_status = resp.status();
+        let listing = match resp.json::<Listing>().await {
+            Ok(v) => v,
+            Err(_) if !http_status.is_success() => bail!("http status {http_status}"),
+            Err(e) => return Err(e.into()),
+        };
+
+        if listing.status.error_code != 0 {
+            bail!(
+                "error {}: {}",
+                listing.status.error_code,
+                listing.status.error_message.unwrap_or_default()
+            );
+        }
+
+        Ok(listing
+            .data
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                let quote = item.quote.get("USD")?;
+                Some(Coin {
+                    rank: item.cmc_rank.unwrap_or(index as u32 + 1),
+                    price: quote.price?,
+                    market_cap: quote.market_cap.unwrap_or_default(),
+                    volume_24h: quote.volume_24h.unwrap_or_default(),
+                    percent_change_1h: quote.percent_change_1h,
+                    percent_change_24h: quote.percent_change_24h,
+                    percent_change_7d: quote.percent_change_7d,
+                    circulating_supply: item.circulating_supply,
+                    total_supply: item.total_supply,
+                    max_supply: item.max_supply,
+                    last_updated: parse_timestamp(item.last_updated.as_deref()),
+                    symbol: item.symbol,
+                    name: item.name,
+                })
+            })
+            .collect())
+    }
+}
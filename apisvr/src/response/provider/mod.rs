@@ -0,0 +1,231 @@
+pub mod coingecko;
+pub mod coinmarketcap;
+
+use crate::conf;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// A coin as every provider reports it, prices are in USD.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Coin {
+    pub symbol: String,
+    pub name: String,
+    pub rank: u32,
+    pub price: f64,
+    pub market_cap: f64,
+    pub volume_24h: f64,
+    pub percent_change_1h: Option<f64>,
+    pub percent_change_24h: Option<f64>,
+    pub percent_change_7d: Option<f64>,
+    pub circulating_supply: Option<f64>,
+    pub total_supply: Option<f64>,
+    pub max_supply: Option<f64>,
+    pub last_updated: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Latest {
+    pub source: String,
+    pub fetched_at: i64,
+    pub coins: Vec<Coin>,
+}
+
+#[rocket::async_trait]
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The top `limit` coins by market cap. An error or a list failing
+    // `validate_coins` makes the caller fail over to the next provider.
+    async fn latest(&self, limit: usize) -> Result<Vec<Coin>>;
+}
+
+pub fn parse_timestamp(v: Option<&str>) -> i64 {
+    v.and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|v| v.timestamp())
+        .unwrap_or_default()
+}
+
+// Builds the providers in the configured order, unknown names are skipped.
+pub fn providers() -> Vec<Box<dyn PriceProvider>> {
+    conf::price()
+        .providers
+        .iter()
+        .filter_map(|name| -> Option<Box<dyn PriceProvider>> {
+            match name.as_str() {
+                coinmarketcap::NAME => Some(Box::new(coinmarketcap::CoinMarketCap::default())),
+                coingecko::NAME => Some(Box::new(coingecko::CoinGecko::default())),
+                _ => {
+                    log::warn!("unknown price provider: {name}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// A listing is used only as a whole, one coin without a positive price makes
+// the caller fail over to the next provider.
+pub fn validate_coins(coins: &[Coin]) -> Result<()> {
+    if coins.is_empty() {
+        bail!("empty listing");
+    }
+
+    match coins
+        .iter()
+        .find(|c| c.symbol.trim().is_empty() || !c.price.is_finite() || c.price <= 0.0)
+    {
+        Some(c) => bail!("invalid price {} of `{}`", c.price, c.symbol),
+        None => Ok(()),
+    }
+}
+
+pub async fn fetch_latest(providers: &[Box<dyn PriceProvider>], limit: usize) -> Result<Latest> {
+    let mut errors = vec![];
+
+    for provider in providers {
+        match provider.latest(limit).await.and_then(|coins| {
+            validate_coins(&coins)?;
+            Ok(coins)
+        }) {
+            Ok(coins) => {
+                return Ok(Latest {
+                    source: provider.name().to_string(),
+                    fetched_at: Utc::now().timestamp(),
+                    coins,
+                })
+            }
+            Err(e) => {
+                log::warn!("price provider {} error: {e:?}", provider.name());
+                errors.push(format!("{}: {e}", provider.name()));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        bail!("no price provider is configured");
+    }
+    bail!("all price providers failed: {}", errors.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::{mock, upstream};
+    use rocket::tokio;
+
+    const CMC_LISTING: &str = r#"{
+        "status": {"error_code": 0, "error_message": null},
+        "data": [
+            {"name": "Bitcoin", "symbol": "BTC", "cmc_rank": 1,
+             "circulating_supply": 19650000, "total_supply": 19650000, "max_supply": 21000000,
+             "last_updated": "2024-03-01T00:00:00.000Z",
+             "quote": {"USD": {"price": 61000.5, "volume_24h": 1.0e10, "market_cap": 1.2e12,
+                               "percent_change_1h": 0.1, "percent_change_24h": 2.5,
+                               "percent_change_7d": -1.0}}}
+        ]
+    }"#;
+
+    const CG_MARKETS: &str = r#"[
+        {"id": "bitcoin", "symbol": "btc", "name": "Bitcoin", "current_price": 61000.5,
+         "market_cap": 1.2e12, "market_cap_rank": 1, "total_volume": 1.0e10,
+         "circulating_supply": 19650000, "total_supply": 19650000, "max_supply": 21000000,
+         "price_change_percentage_1h_in_currency": 0.1,
+         "price_change_percentage_24h_in_currency": 2.5,
+         "price_change_percentage_7d_in_currency": -1.0,
+         "last_updated": "2024-03-01T00:00:00.000Z"}
+    ]"#;
+
+    fn both(server: &mock::Server) -> Vec<Box<dyn PriceProvider>> {
+        vec![
+            Box::new(coinmarketcap::CoinMarketCap::new(&server.base_url, "key")),
+            Box::new(coingecko::CoinGecko::new(&server.base_url, "")),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_same_shape() -> Result<()> {
+        let server = mock::Server::start().await;
+        server
+            .route(coinmarketcap::LISTINGS_PATH, 200, CMC_LISTING)
+            .route(coingecko::MARKETS_PATH, 200, CG_MARKETS);
+
+        let cmc = coinmarketcap::CoinMarketCap::new(&server.base_url, "key")
+            .latest(10)
+            .await?;
+        let cg = coingecko::CoinGecko::new(&server.base_url, "")
+            .latest(10)
+            .await?;
+
+        assert_eq!(cmc, cg);
+        assert_eq!(cmc[0].symbol, "BTC");
+        assert_eq!(cmc[0].last_updated, 1709251200);
+        assert_eq!(cmc[0].max_supply, Some(21000000.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failover() -> Result<()> {
+        let server = mock::Server::start().await;
+        server
+            .route(
+                coinmarketcap::LISTINGS_PATH,
+                200,
+                r#"{"status": {"error_code": 1008, "error_message": "rate limit"}}"#,
+            )
+            .route(coingecko::MARKETS_PATH, 200, CG_MARKETS);
+
+        let latest = fetch_latest(&both(&server), 10).await?;
+        assert_eq!(latest.source, coingecko::NAME);
+        assert_eq!(latest.coins.len(), 1);
+        assert_eq!(server.hits(coinmarketcap::LISTINGS_PATH), 1);
+
+        server.route(coinmarketcap::LISTINGS_PATH, 200, CMC_LISTING);
+        let latest = fetch_latest(&both(&server), 10).await?;
+        assert_eq!(latest.source, coinmarketcap::NAME);
+        assert_eq!(server.hits(coingecko::MARKETS_PATH), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_failed() {
+        let server = mock::Server::start().await;
+        server.route(coinmarketcap::LISTINGS_PATH, 401, "{}").route(
+            coingecko::MARKETS_PATH,
+            200,
+            "not json",
+        );
+
+        let e = fetch_latest(&both(&server), 10).await.unwrap_err();
+        assert!(e.to_string().contains(coinmarketcap::NAME));
+        assert!(e.to_string().contains(coingecko::NAME));
+
+        // Every mock server has a breaker of its own.
+        let breakers = upstream::breakers();
+        for name in [coinmarketcap::NAME, coingecko::NAME] {
+            let name = format!("{name} {}", server.base_url);
+            assert!(breakers.iter().any(|b| b.name == name), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_listing() -> Result<()> {
+        let server = mock::Server::start().await;
+        server
+            .route(
+                coinmarketcap::LISTINGS_PATH,
+                200,
+                CMC_LISTING.replace("61000.5", "0"),
+            )
+            .route(coingecko::MARKETS_PATH, 200, CG_MARKETS);
+
+        let latest = fetch_latest(&both(&server), 10).await?;
+        assert_eq!(latest.source, coingecko::NAME);
+
+        server.route(coingecko::MARKETS_PATH, 200, "[]");
+        let e = fetch_latest(&both(&server), 10).await.unwrap_err();
+        assert!(e.to_string().contains("invalid price 0 of `BTC`"));
+        assert!(e.to_string().contains("empty listing"));
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+// Trading sessions of the exchanges in the `market` config, evaluated in their
+// local time.
+use crate::config::{Exchange, Market};
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Session {
+    Live,
+
+    // The quote is the closing value of the last session.
+    Closed,
+}
+
+// Minutes since midnight of `HH:MM`, `24:00` is the end of the day.
+fn minutes(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    (m < 60 && h * 60 + m <= 24 * 60).then_some(h * 60 + m)
+}
+
+fn session(s: &str) -> Option<(u32, u32)> {
+    let (open, close) = s.split_once('-')?;
+    Some((minutes(open)?, minutes(close)?))
+}
+
+fn local_time(exchange: &Exchange, now: DateTime<Utc>) -> Option<DateTime<Tz>> {
+    match exchange.timezone.parse::<Tz>() {
+        Ok(tz) => Some(now.with_timezone(&tz)),
+        Err(_) => {
+            log::warn!("invalid timezone `{}`", exchange.timezone);
+            None
+        }
+    }
+}
+
+fn is_trading_day(exchange: &Exchange, local: &DateTime<Tz>) -> bool {
+    let weekday = exchange
+        .weekdays
+        .iter()
+        .any(|d| d.parse::<Weekday>().is_ok_and(|d| d == local.weekday()));
+    let holiday = exchange
+        .holidays
+        .iter()
+        .any(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok_and(|d| d == local.date_naive()));
+    weekday && !holiday
+}
+
+// A misconfigured exchange counts as open, a typo must not stop polling.
+pub fn is_open(exchange: &Exchange, now: DateTime<Utc>) -> bool {
+    let Some(local) = local_time(exchange, now) else {
+        return true;
+    };
+    if !is_trading_day(exchange, &local) {
+        return false;
+    }
+
+    let minute = local.hour() * 60 + local.minute();
+    exchange.sessions.iter().any(|s| match session(s) {
+        Some((open, close)) => (open..close).contains(&minute),
+        None => {
+            log::warn!("invalid session `{s}`");
+            true
+        }
+    })
+}
+
+fn exchange_of<'a>(conf: &'a Market, code: &str) -> Option<&'a Exchange> {
+    conf.exchanges
+        .values()
+        .find(|e| e.products.iter().any(|p| p == code))
+}
+
+// The local date of the trading day `code` is in, from the first open of the
+// day on. Before that the quote is still the close of the day before.
+pub fn trading_date(conf: &Market, code: &str, now: DateTime<Utc>) -> Option<NaiveDate> {
+    let Some(exchange) = exchange_of(conf, code) else {
+        return Some(now.date_naive());
+    };
+    let Some(local) = local_time(exchange, now) else {
+        return Some(now.date_naive());
+    };
+
+    let minute = local.hour() * 60 + local.minute();
+    let opened = exchange
+        .sessions
+        .iter()
+        .filter_map(|s| session(s))
+        .any(|(open, _)| open <= minute);
+    (is_trading_day(exchange, &local) && opened).then(|| local.date_naive())
+}
+
+pub fn state(conf: &Market, code: &str, now: DateTime<Utc>) -> Session {
+    match exchange_of(conf, code) {
+        Some(e) if !is_open(e, now) => Session::Closed,
+        _ => Session::Live,
+    }
+}
+
+// Whether any configured product is being traded.
+pub fn any_live(conf: &Market, now: DateTime<Utc>) -> bool {
+    conf.products
+        .iter()
+        .any(|p| state(conf, p, now) == Session::Live)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn t(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().into()
+    }
+
+    #[test]
+    fn test_is_open() {
+        let conf = Market::default();
+        let sse = &conf.exchanges["sse"];
+
+        // 2024-03-04 is a Monday, Shanghai is UTC+8.
+        assert!(is_open(sse, t("2024-03-04T01:30:00Z")));
+        assert!(!is_open(sse, t("2024-03-04T03:45:00Z")));
+        assert!(is_open(sse, t("2024-03-04T06:59:00Z")));
+        assert!(!is_open(sse, t("2024-03-04T07:00:00Z")));
+        assert!(!is_open(sse, t("2024-03-09T02:00:00Z")));
+
+        let holiday = Exchange {
+            holidays: vec!["2024-03-04".to_string()],
+            ..sse.clone()
+        };
+        assert!(!is_open(&holiday, t("2024-03-04T01:30:00Z")));
+
+        // New York follows daylight saving time, 09:30 is 14:30 UTC in
+        // winter and 13:30 UTC in summer.
+        let us = &conf.exchanges["us"];
+        assert!(!is_open(us, t("2024-03-04T14:00:00Z")));
+        assert!(is_open(us, t("2024-03-04T14:30:00Z")));
+        assert!(is_open(us, t("2024-07-01T13:30:00Z")));
+
+        let fx = &conf.exchanges["fx"];
+        assert!(is_open(
+            fx,
+            Utc.with_ymd_and_hms(2024, 3, 5, 3, 59, 0).unwrap()
+        ));
+        assert!(!is_open(fx, t("2024-03-09T12:00:00Z")));
+
+        let typo = Exchange {
+            timezone: "Asia/Shanghi".to_string(),
+            ..sse.clone()
+        };
+        assert!(is_open(&typo, t("2024-03-09T02:00:00Z")));
+    }
+
+    #[test]
+    fn test_state() {
+        let conf = Market::default();
+        let now = t("2024-03-04T02:00:00Z");
+        assert_eq!(state(&conf, "000001.SS", now), Session::Live);
+        assert_eq!(state(&conf, "US500.OTC", now), Session::Closed);
+        assert_eq!(state(&conf, "UNLISTED.OTC", now), Session::Live);
+        assert!(any_live(&conf, now));
+        assert!(!any_live(&conf, t("2024-03-10T02:00:00Z")));
+    }
+
+    #[test]
+    fn test_trading_date() {
+        let conf = Market::default();
+        let date = |v| NaiveDate::parse_from_str(v, "%Y-%m-%d").ok();
+
+        // 09:00 and 20:00 in Shanghai, before and after the sessions.
+        assert_eq!(
+            trading_date(&conf, "000001.SS", t("2024-03-04T01:00:00Z")),
+            None
+        );
+        assert_eq!(
+            trading_date(&conf, "000001.SS", t("2024-03-04T12:00:00Z")),
+            date("2024-03-04")
+        );
+        assert_eq!(
+            trading_date(&conf, "000001.SS", t("2024-03-09T12:00:00Z")),
+            None
+        );
+
+        // Friday evening in New York is already Saturday in UTC.
+        assert_eq!(
+            trading_date(&conf, "US500.OTC", t("2024-03-09T01:00:00Z")),
+            date("2024-03-08")
+        );
+        assert_eq!(
+            trading_date(&conf, "UNLISTED.OTC", t("2024-03-09T01:00:00Z")),
+            date("2024-03-09")
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::conf;
+use rocket::tokio::sync::broadcast;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+lazy_static! {
+    static ref HUB: Mutex<Hub> = Mutex::new(Hub::new(conf::stream().buffer_size));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Market,
+    Stats,
+    Latest,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 3] = [Topic::Market, Topic::Stats, Topic::Latest];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Topic::Market => "market",
+            Topic::Stats => "stats",
+            Topic::Latest => "latest",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u64,
+    pub topic: Topic,
+    pub data: String,
+}
+
+pub struct Hub {
+    next_id: u64,
+    capacity: usize,
+    ring: VecDeque<Message>,
+
+    // The last message of every topic, to drop refreshes that changed nothing.
+    current: HashMap<Topic, Message>,
+    tx: broadcast::Sender<Message>,
+}
+
+impl Hub {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            next_id: 1,
+            capacity,
+            ring: VecDeque::with_capacity(capacity),
+            current: HashMap::new(),
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    // Returns the new message, or `None` when `data` equals the current value.
+    pub fn publish(&mut self, topic: Topic, data: String) -> Option<Message> {
+        if self.current.get(&topic).is_some_and(|m| m.data == data) {
+            return None;
+        }
+
+        let msg = Message {
+            id: self.next_id,
+            topic,
+            data,
+        };
+        self.next_id += 1;
+
+        if self.ring.len() == self.capacity {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(msg.clone());
+        self.current.insert(topic, msg.clone());
+
+        // No receivers is not an error, nobody is listening yet.
+        let _ = self.tx.send(msg.clone());
+        Some(msg)
+    }
+
+    // The messages a client missed after `last_id` followed by a receiver for
+    // new ones. Clients without an id, or whose id fell out of the ring or
+    // belongs to an earlier run, get the current value of every topic instead.
+    pub fn subscribe(&self, last_id: Option<u64>) -> (Vec<Message>, broadcast::Receiver<Message>) {
+        let is_replayable = last_id.is_some_and(|id| {
+            id < self.next_id && self.ring.front().is_none_or(|m| m.id <= id + 1)
+        });
+
+        let backlog = match last_id {
+            Some(id) if is_replayable => self.ring.iter().filter(|m| m.id > id).cloned().collect(),
+            _ => {
+                let mut v = self.current.values().cloned().collect::<Vec<_>>();
+                v.sort_by_key(|m| m.id);
+                v
+            }
+        };
+
+        (backlog, self.tx.subscribe())
+    }
+}
+
+pub fn publish(topic: Topic, data: String) {
+    HUB.lock().unwrap().publish(topic, data);
+}
+
+pub fn subscribe(last_id: Option<u64>) -> (Vec<Message>, broadcast::Receiver<Message>) {
+    HUB.lock().unwrap().subscribe(last_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(v: &[Message]) -> Vec<u64> {
+        v.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn test_publish() {
+        let mut hub = Hub::new(3);
+        let (_, mut rx) = hub.subscribe(None);
+
+        assert_eq!(hub.publish(Topic::Market, "1".to_string()).unwrap().id, 1);
+        assert!(hub.publish(Topic::Market, "1".to_string()).is_none());
+        assert_eq!(hub.publish(Topic::Stats, "1".to_string()).unwrap().id, 2);
+        assert_eq!(hub.publish(Topic::Market, "2".to_string()).unwrap().id, 3);
+
+        assert_eq!(rx.try_recv().unwrap().id, 1);
+        assert_eq!(rx.try_recv().unwrap().id, 2);
+        assert_eq!(rx.try_recv().unwrap().data, "2");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut hub = Hub::new(3);
+        assert!(hub.subscribe(None).0.is_empty());
+
+        for (topic, data) in [
+            (Topic::Market, "1"),
+            (Topic::Stats, "1"),
+            (Topic::Market, "2"),
+            (Topic::Latest, "1"),
+            (Topic::Market, "3"),
+        ] {
+            hub.publish(topic, data.to_string());
+        }
+
+        // The ring holds 3..=5.
+        assert_eq!(ids(&hub.subscribe(Some(2)).0), vec![3, 4, 5]);
+        assert_eq!(ids(&hub.subscribe(Some(4)).0), vec![5]);
+        assert!(hub.subscribe(Some(5)).0.is_empty());
+
+        // Too old or from an earlier run, resync with the current values.
+        assert_eq!(ids(&hub.subscribe(Some(1)).0), vec![2, 4, 5]);
+        assert_eq!(ids(&hub.subscribe(Some(42)).0), vec![2, 4, 5]);
+        assert_eq!(ids(&hub.subscribe(None).0), vec![2, 4, 5]);
+    }
+}
@@ -0,0 +1,311 @@
+// The HTTP layer every fetcher goes through. Requests share a client with
+// timeouts, are retried with backoff and are guarded by a circuit breaker per
+// provider.
+use crate::{conf, config::Upstream};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use rand::Rng;
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+use rocket::tokio::{self, time::Duration};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::BTreeMap, sync::Mutex};
+
+lazy_static! {
+    static ref CLIENTS: Mutex<BTreeMap<bool, Client>> = Mutex::new(BTreeMap::new());
+    static ref BREAKERS: Mutex<BTreeMap<String, Breaker>> = Mutex::new(BTreeMap::new());
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    #[default]
+    Closed,
+    Open,
+
+    // The open period is over and one trial request is on its way. Another
+    // one goes out at `retry_at` in case the result of the first never comes.
+    HalfOpen,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Breaker {
+    pub name: String,
+    pub state: State,
+    pub consecutive_failures: u32,
+    pub opened_at: Option<i64>,
+    pub retry_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl Breaker {
+    // `trial_secs` is how long a trial may take before another is let through.
+    pub fn allow(&mut self, now: i64, trial_secs: i64) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open | State::HalfOpen if self.retry_at.is_some_and(|t| now >= t) => {
+                self.state = State::HalfOpen;
+                self.retry_at = Some(now + trial_secs);
+                true
+            }
+            State::Open | State::HalfOpen => false,
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.state = State::Closed;
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+        self.retry_at = None;
+    }
+
+    // A failed trial opens the breaker again right away.
+    pub fn fail(&mut self, now: i64, threshold: u32, open_secs: i64, error: String) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error);
+
+        if self.state == State::HalfOpen || self.consecutive_failures >= threshold.max(1) {
+            self.state = State::Open;
+            self.opened_at = Some(now);
+            self.retry_at = Some(now + open_secs);
+        }
+    }
+}
+
+// A job timeout that never cuts short `calls` requests sent one after another.
+pub fn job_timeout_secs(calls: usize) -> u64 {
+    const MARGIN_SECS: u64 = 10;
+    conf::upstream().budget_secs() * calls.max(1) as u64 + MARGIN_SECS
+}
+
+pub fn breakers() -> Vec<Breaker> {
+    BREAKERS.lock().unwrap().values().cloned().collect()
+}
+
+fn with_breaker<T>(name: &str, f: impl FnOnce(&mut Breaker) -> T) -> T {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers.entry(name.to_string()).or_insert_with(|| Breaker {
+        name: name.to_string(),
+        ..Default::default()
+    });
+    f(breaker)
+}
+
+fn client(use_proxy: bool) -> Result<Client> {
+    if let Some(c) = CLIENTS.lock().unwrap().get(&use_proxy) {
+        return Ok(c.clone());
+    }
+
+    let conf = conf::upstream();
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(conf.connect_timeout_secs))
+        .timeout(Duration::from_secs(conf.timeout_secs));
+
+    if use_proxy {
+        let socket5 = conf::socket5();
+        builder = builder.proxy(Proxy::all(format!(
+            "socks5://{}:{}",
+            socket5.ip, socket5.port
+        ))?);
+    }
+
+    let c = builder.build()?;
+    CLIENTS.lock().unwrap().insert(use_proxy, c.clone());
+    Ok(c)
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Somewhere in the upper half of `base_ms * 2^(attempt - 1)`, capped at
+// `max_ms`, so that clients retrying together spread out.
+pub fn backoff(attempt: u32, base_ms: u64, max_ms: u64) -> Duration {
+    let ms = base_ms
+        .saturating_mul(2_u64.saturating_pow(attempt.saturating_sub(1)))
+        .min(max_ms);
+    Duration::from_millis(rand::thread_rng().gen_range(ms / 2..=ms))
+}
+
+// Sends the request built by `request` on behalf of the provider `name`.
+// Responses other than 429 and 5xx count as success and are returned as they
+// are, the caller decides what a 4xx means.
+pub async fn send(
+    name: &str,
+    use_proxy: bool,
+    request: impl Fn(&Client) -> RequestBuilder,
+) -> Result<Response> {
+    send_with(&conf::upstream(), name, use_proxy, request).await
+}
+
+pub async fn send_with(
+    conf: &Upstream,
+    name: &str,
+    use_proxy: bool,
+    request: impl Fn(&Client) -> RequestBuilder,
+) -> Result<Response> {
+    let trial_secs = conf.budget_secs() as i64;
+    if !with_breaker(name, |b| b.allow(Utc::now().timestamp(), trial_secs)) {
+        bail!("circuit breaker of {name} is open");
+    }
+
+    let client = client(use_proxy)?;
+    let mut error = anyhow!("no request was sent");
+
+    for attempt in 0..conf.max_attempts.max(1) {
+        if attempt > 0 {
+            tokio::time::sleep(backoff(attempt, conf.backoff_ms, conf.max_backoff_ms)).await;
+        }
+
+        match request(&client).send().await {
+            Ok(resp) if is_retryable(resp.status()) => {
+                error = anyhow!("http status {}", resp.status());
+            }
+            Ok(resp) => {
+                with_breaker(name, Breaker::succeed);
+                return Ok(resp);
+            }
+            Err(e) => error = e.into(),
+        }
+    }
+
+    with_breaker(name, |b| {
+        b.fail(
+            Utc::now().timestamp(),
+            conf.failure_threshold,
+            conf.open_secs,
+            format!("{error:#}"),
+        )
+    });
+    Err(error)
+}
+
+// The body of a successful response parsed as `T`, anything else is an error
+// that must not reach a cache.
+pub async fn json<T: DeserializeOwned>(resp: Response) -> Result<T> {
+    let status = resp.status();
+    if !status.is_success() {
+        bail!("http status {status}");
+    }
+
+    resp.json::<T>()
+        .await
+        .map_err(|e| anyhow!("unexpected response: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::mock;
+
+    #[test]
+    fn test_breaker() {
+        let mut b = Breaker::default();
+        for now in 0..2 {
+            assert!(b.allow(now, 30));
+            b.fail(now, 3, 60, "http status 500".to_string());
+        }
+        assert_eq!(b.state, State::Closed);
+
+        b.fail(2, 3, 60, "http status 500".to_string());
+        assert_eq!(b.state, State::Open);
+        assert_eq!(b.retry_at, Some(62));
+        assert!(!b.allow(61, 30));
+
+        // One trial after the open period, a failed one opens again.
+        assert!(b.allow(62, 30));
+        assert_eq!(b.state, State::HalfOpen);
+        assert!(!b.allow(62, 30));
+        b.fail(63, 3, 60, "timeout".to_string());
+        assert_eq!((b.state, b.retry_at), (State::Open, Some(123)));
+
+        // A trial whose result never comes is followed by another one.
+        assert!(b.allow(123, 30));
+        assert!(!b.allow(152, 30));
+        assert!(b.allow(153, 30));
+        assert_eq!((b.state, b.retry_at), (State::HalfOpen, Some(183)));
+
+        b.succeed();
+        assert_eq!((b.state, b.consecutive_failures), (State::Closed, 0));
+        assert!(b.allow(154, 30));
+    }
+
+    #[test]
+    fn test_budget() {
+        // 3 attempts of 15s with 0.5s and 1s between them.
+        let conf = Upstream::default();
+        assert_eq!(conf.budget_secs(), 47);
+
+        let conf = Upstream {
+            max_attempts: 5,
+            max_backoff_ms: 1500,
+            ..Default::default()
+        };
+        assert_eq!(conf.budget_secs(), 75 + 5);
+    }
+
+    #[test]
+    fn test_backoff() {
+        for _ in 0..100 {
+            let d = backoff(1, 500, 10000).as_millis();
+            assert!((250..=500).contains(&d));
+            let d = backoff(3, 500, 10000).as_millis();
+            assert!((1000..=2000).contains(&d));
+            let d = backoff(10, 500, 10000).as_millis();
+            assert!((5000..=10000).contains(&d));
+        }
+    }
+
+    #[rocket::tokio::test]
+    async fn test_send() {
+        let server = mock::Server::start().await;
+        server
+            .route("/ok", 200, "{}")
+            .route("/missing", 404, "{}")
+            .route("/down", 503, "{}");
+        let url = |path: &str| format!("{}{path}", server.base_url);
+        let conf = Upstream {
+            backoff_ms: 1,
+            max_backoff_ms: 1,
+            ..Default::default()
+        };
+
+        let resp = send_with(&conf, "test-ok", false, |c| c.get(url("/ok")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = send_with(&conf, "test-ok", false, |c| c.get(url("/missing")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(server.hits("/missing"), 1);
+        let e = json::<serde_json::Value>(resp).await.unwrap_err();
+        assert_eq!(e.to_string(), "http status 404 Not Found");
+
+        // Retried until the attempts run out, then the breaker opens and no
+        // more requests are sent.
+        for _ in 0..conf.failure_threshold {
+            let e = send_with(&conf, "test-down", false, |c| c.get(url("/down")))
+                .await
+                .unwrap_err();
+            assert!(e.to_string().contains("503"));
+        }
+        let hits = server.hits("/down");
+        assert_eq!(hits, (conf.failure_threshold * conf.max_attempts) as usize);
+
+        let e = send_with(&conf, "test-down", false, |c| c.get(url("/down")))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("circuit breaker"));
+        assert_eq!(server.hits("/down"), hits);
+
+        let breakers = breakers();
+        let down = breakers.iter().find(|b| b.name == "test-down").unwrap();
+        assert_eq!(down.state, State::Open);
+        assert_eq!(
+            down.last_error.as_deref(),
+            Some("http status 503 Service Unavailable")
+        );
+    }
+}
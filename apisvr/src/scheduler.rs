@@ -0,0 +1,433 @@
+use crate::{conf, config::JobOverride, response::RUNTIME};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rocket::tokio::{
+    self,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{Duration, Instant},
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+// The clock jobs are scheduled by. Tests follow tokio's clock instead, so a
+// paused runtime advances the schedule with its timers.
+#[cfg(not(test))]
+fn now() -> DateTime<Utc> {
+    Utc::now()
+}
+
+#[cfg(test)]
+fn now() -> DateTime<Utc> {
+    static START: std::sync::OnceLock<(DateTime<Utc>, Instant)> = std::sync::OnceLock::new();
+    let (at, instant) = START.get_or_init(|| (Utc::now(), Instant::now()));
+    *at + chrono::Duration::from_std(Instant::now().saturating_duration_since(*instant))
+        .unwrap_or_default()
+}
+
+type Task = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+lazy_static! {
+    static ref STATUS: Mutex<BTreeMap<String, JobStatus>> = Mutex::new(BTreeMap::new());
+}
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    // The first slot after `prev` that is still ahead of `now`. Missed slots
+    // are skipped rather than run back to back.
+    pub fn next_after(&self, prev: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Interval(d) => {
+                let d = chrono::Duration::from_std(*d)
+                    .ok()?
+                    .max(chrono::Duration::milliseconds(1));
+                let mut next = prev + d;
+                if next <= now {
+                    let missed = (now - next).num_milliseconds() / d.num_milliseconds() + 1;
+                    next += d * missed as i32;
+                }
+                Some(next)
+            }
+            Schedule::Cron(s) => s.after(&prev.max(now)).next(),
+        }
+    }
+
+    // The time between two runs, for a cron schedule the gap between its next
+    // two slots.
+    pub fn interval(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Interval(d) => Some(*d),
+            Schedule::Cron(s) => {
+                let mut slots = s.after(&now);
+                let (a, b) = (slots.next()?, slots.next()?);
+                (b - a).to_std().ok()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Interval(d) => write!(f, "every {d:?}"),
+            Schedule::Cron(s) => write!(f, "cron {s}"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub interval_secs: Option<u64>,
+    pub jitter_secs: u64,
+    pub timeout_secs: u64,
+    pub max_concurrency: usize,
+    pub running: usize,
+    pub runs: u64,
+    pub failures: u64,
+
+    // Slots dropped because `max_concurrency` runs were still going.
+    pub skipped: u64,
+    pub last_run_at: Option<i64>,
+    pub last_duration_ms: Option<u64>,
+    pub last_outcome: Option<Outcome>,
+    pub last_error: Option<String>,
+    pub next_run_at: Option<i64>,
+}
+
+pub struct Job {
+    pub name: String,
+    pub schedule: Schedule,
+    pub jitter: Duration,
+    pub timeout: Duration,
+    pub max_concurrency: usize,
+
+    // When an earlier process last ran the job, an interval job waits for
+    // its next slot instead of running right away.
+    pub last_run: Option<DateTime<Utc>>,
+    task: Task,
+}
+
+impl Job {
+    // A job that runs right away and then every `secs` seconds, one run at a
+    // time, without jitter.
+    pub fn every<F, Fut>(name: &str, secs: u64, task: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            schedule: Schedule::Interval(Duration::from_secs(secs.max(1))),
+            jitter: Duration::ZERO,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            max_concurrency: 1,
+            last_run: None,
+            task: Arc::new(move || Box::pin(task())),
+        }
+    }
+
+    pub fn with_timeout(mut self, secs: u64) -> Self {
+        self.timeout = Duration::from_secs(secs.max(1));
+        self
+    }
+
+    pub fn with_last_run(mut self, at: DateTime<Utc>) -> Self {
+        self.last_run = Some(at);
+        self
+    }
+
+    // An invalid `cron` leaves the schedule as it is, the other fields still
+    // apply.
+    pub fn apply(&mut self, o: &JobOverride) -> Result<()> {
+        if let Some(secs) = o.interval_secs {
+            self.schedule = Schedule::Interval(Duration::from_secs(secs.max(1)));
+        }
+        if let Some(secs) = o.jitter_secs {
+            self.jitter = Duration::from_secs(secs);
+        }
+        if let Some(secs) = o.timeout_secs {
+            self.timeout = Duration::from_secs(secs.max(1));
+        }
+        if let Some(n) = o.max_concurrency {
+            self.max_concurrency = n.max(1);
+        }
+
+        if let Some(expr) = &o.cron {
+            let s = cron::Schedule::from_str(expr)
+                .map_err(|e| anyhow!("invalid cron `{expr}` of job {}: {e}", self.name))?;
+            self.schedule = Schedule::Cron(Box::new(s));
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> JobStatus {
+        JobStatus {
+            name: self.name.clone(),
+            schedule: self.schedule.to_string(),
+            interval_secs: self.schedule.interval(now()).map(|d| d.as_secs()),
+            jitter_secs: self.jitter.as_secs(),
+            timeout_secs: self.timeout.as_secs(),
+            max_concurrency: self.max_concurrency,
+            ..Default::default()
+        }
+    }
+}
+
+// Applies the config override of the job and starts it on the shared runtime.
+pub fn spawn(mut job: Job) {
+    if let Some(o) = conf::scheduler().jobs.get(&job.name) {
+        if let Err(e) = job.apply(o) {
+            log::warn!("{e:#}");
+        }
+    }
+
+    RUNTIME.lock().unwrap().spawn(run(job));
+}
+
+pub fn statuses() -> Vec<JobStatus> {
+    STATUS.lock().unwrap().values().cloned().collect()
+}
+
+// The shortest interval of the given jobs, how long a value refreshed by them
+// can be cached.
+pub fn interval_secs(names: &[&str]) -> Option<u64> {
+    let status = STATUS.lock().unwrap();
+    names
+        .iter()
+        .filter_map(|n| status.get(*n)?.interval_secs)
+        .min()
+}
+
+fn update_status(name: &str, f: impl FnOnce(&mut JobStatus)) {
+    if let Some(status) = STATUS.lock().unwrap().get_mut(name) {
+        f(status);
+    }
+}
+
+// Every job has its own loop and every run its own task, so neither a slow
+// run nor another job delays the next slot.
+pub async fn run(job: Job) {
+    log::debug!("job {} start, {}", job.name, job.schedule);
+    STATUS
+        .lock()
+        .unwrap()
+        .insert(job.name.clone(), job.status());
+
+    let job = Arc::new(job);
+    let semaphore = Arc::new(Semaphore::new(job.max_concurrency));
+    let mut slot = match &job.schedule {
+        Schedule::Interval(d) => {
+            let next = job
+                .last_run
+                .and_then(|t| Some(t + chrono::Duration::from_std(*d).ok()?));
+            Some(next.filter(|t| *t > now()).unwrap_or_else(now))
+        }
+        Schedule::Cron(_) => job.schedule.next_after(now(), now()),
+    };
+
+    while let Some(at) = slot {
+        let jitter = match job.jitter.as_millis() as u64 {
+            0 => Duration::ZERO,
+            ms => Duration::from_millis(rand::thread_rng().gen_range(0..=ms)),
+        };
+        let run_at = at + chrono::Duration::from_std(jitter).unwrap_or_default();
+        update_status(&job.name, |s| s.next_run_at = Some(run_at.timestamp()));
+
+        let wait = (run_at - now()).to_std().unwrap_or_default();
+        tokio::time::sleep_until(Instant::now() + wait).await;
+
+        match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => {
+                tokio::spawn(run_once(job.clone(), permit));
+            }
+            Err(_) => {
+                log::warn!("job {} skipped, the last run is still going", job.name);
+                update_status(&job.name, |s| s.skipped += 1);
+            }
+        }
+
+        slot = job.schedule.next_after(at, now());
+    }
+
+    update_status(&job.name, |s| s.next_run_at = None);
+}
+
+async fn run_once(job: Arc<Job>, _permit: OwnedSemaphorePermit) {
+    let started_at = now().timestamp();
+    let started = Instant::now();
+    update_status(&job.name, |s| s.running += 1);
+
+    let (outcome, error) = match tokio::time::timeout(job.timeout, (job.task)()).await {
+        Ok(Ok(())) => (Outcome::Ok, None),
+        Ok(Err(e)) => (Outcome::Failed, Some(format!("{e:#}"))),
+        Err(_) => (
+            Outcome::TimedOut,
+            Some(format!("timed out after {:?}", job.timeout)),
+        ),
+    };
+
+    if let Some(e) = &error {
+        log::warn!("job {} error: {e}", job.name);
+    }
+
+    update_status(&job.name, |s| {
+        s.running -= 1;
+        s.runs += 1;
+        if outcome != Outcome::Ok {
+            s.failures += 1;
+        }
+        s.last_run_at = Some(started_at);
+        s.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        s.last_outcome = Some(outcome);
+        s.last_error = error;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn status(name: &str) -> JobStatus {
+        STATUS
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_next_after() {
+        let t = |secs| Utc.timestamp_opt(secs, 0).unwrap();
+        let every = Schedule::Interval(Duration::from_secs(30));
+
+        assert_eq!(every.next_after(t(0), t(1)), Some(t(30)));
+        assert_eq!(every.next_after(t(0), t(30)), Some(t(60)));
+        assert_eq!(every.next_after(t(0), t(95)), Some(t(120)));
+
+        let cron = Schedule::Cron(Box::new(cron::Schedule::from_str("0 */5 * * * *").unwrap()));
+        assert_eq!(cron.next_after(t(0), t(1)), Some(t(300)));
+        assert_eq!(cron.next_after(t(300), t(301)), Some(t(600)));
+
+        assert_eq!(every.interval(t(0)), Some(Duration::from_secs(30)));
+        assert_eq!(cron.interval(t(1)), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut job = Job::every("test", 30, || async { Ok(()) });
+        job.apply(&JobOverride {
+            jitter_secs: Some(5),
+            max_concurrency: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(job.schedule.to_string(), "every 30s");
+        assert_eq!((job.jitter.as_secs(), job.max_concurrency), (5, 1));
+
+        job.apply(&JobOverride {
+            cron: Some("0 0 * * * *".to_string()),
+            interval_secs: Some(10),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(job.schedule, Schedule::Cron(_)));
+
+        let mut job = Job::every("test", 30, || async { Ok(()) });
+        assert!(job
+            .apply(&JobOverride {
+                cron: Some("every minute".to_string()),
+                timeout_secs: Some(5),
+                ..Default::default()
+            })
+            .is_err());
+        assert_eq!(job.schedule.to_string(), "every 30s");
+        assert_eq!(job.timeout.as_secs(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run() {
+        let job = |name: &str, sleep: u64, timeout: u64| Job {
+            schedule: Schedule::Interval(Duration::from_millis(50)),
+            timeout: Duration::from_millis(timeout),
+            ..Job::every(name, 1, move || async move {
+                tokio::time::sleep(Duration::from_millis(sleep)).await;
+                Ok(())
+            })
+        };
+
+        let handles = [
+            tokio::spawn(run(job("test-ok", 0, 1000))),
+            tokio::spawn(run(job("test-timeout", 200, 10))),
+            tokio::spawn(run(job("test-busy", 180, 1000))),
+            tokio::spawn(run(Job::every("test-fail", 1, || async {
+                Err(anyhow!("upstream is down"))
+            }))),
+        ];
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        handles.iter().for_each(|h| h.abort());
+
+        let ok = status("test-ok");
+        assert!(ok.runs >= 4);
+        assert_eq!((ok.failures, ok.skipped), (0, 0));
+        assert_eq!(ok.last_outcome, Some(Outcome::Ok));
+        assert!(ok.next_run_at.is_some());
+
+        let timeout = status("test-timeout");
+        assert_eq!(timeout.last_outcome, Some(Outcome::TimedOut));
+        assert_eq!(timeout.failures, timeout.runs);
+
+        let busy = status("test-busy");
+        assert!(busy.skipped >= 2);
+        assert!(busy.runs >= 1);
+
+        let fail = status("test-fail");
+        assert_eq!(fail.last_outcome, Some(Outcome::Failed));
+        assert_eq!(fail.last_error.as_deref(), Some("upstream is down"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_last_run() {
+        let job = |name: &str, secs_ago| {
+            Job::every(name, 60, || async { Ok(()) })
+                .with_last_run(now() - chrono::Duration::seconds(secs_ago))
+        };
+
+        let handles = [
+            tokio::spawn(run(job("test-recent", 20))),
+            tokio::spawn(run(job("test-overdue", 90))),
+        ];
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(status("test-recent").runs, 0);
+        assert_eq!(status("test-overdue").runs, 1);
+
+        tokio::time::sleep(Duration::from_secs(40)).await;
+        handles.iter().for_each(|h| h.abort());
+        assert_eq!(status("test-recent").runs, 1);
+    }
+}